@@ -0,0 +1,202 @@
+//! Measure the throughput of a running bottlecache instance under concurrent load
+//!
+//! Write a year of results for a few testsuites to a directory:
+//!
+//! ```text
+//! # cargo run --release --example load -- --write-results /tmp/results
+//! ```
+//!
+//! Start an instance serving them:
+//!
+//! ```text
+//! # cargo run --release -- --source dir:/tmp/results
+//! ```
+//!
+//! And then hammer it with requests on every endpoint:
+//!
+//! ```text
+//! # cargo run --release --example load -- --clients 64 --requests 500
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::{Days, NaiveDate};
+use structopt::StructOpt;
+
+use common::{RunResults, TestsuiteResult};
+
+/// Testsuites and number of days of results written by `--write-results`
+const TESTSUITES: usize = 8;
+const DAYS: u64 = 365;
+
+#[derive(StructOpt, Debug)]
+struct Args {
+    #[structopt(
+        long,
+        default_value = "http://127.0.0.1:8000",
+        help = "bottlecache instance"
+    )]
+    url: String,
+    #[structopt(long, default_value = "64", help = "Number of concurrent clients")]
+    clients: usize,
+    #[structopt(long, default_value = "500", help = "Number of requests per client")]
+    requests: usize,
+    #[structopt(
+        long,
+        help = "Write results to serve to this directory instead of measuring anything"
+    )]
+    write_results: Option<PathBuf>,
+}
+
+/// Write the same results on every run, so that measurements can be compared
+fn write_results(dir: &Path) {
+    fs::create_dir_all(dir).expect("couldn't create results directory");
+    let first = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+
+    for testsuite in 0..TESTSUITES {
+        for day in 0..DAYS {
+            let tests = 10_000 + day * 10;
+            let failures = (day * 7 + testsuite as u64 * 13) % 100;
+            let json = TestsuiteResult {
+                name: format!("testsuite-{testsuite}"),
+                commit: format!("{:09x}", day * 31 + testsuite as u64),
+                date: first + Days::new(day),
+                results: RunResults {
+                    tests,
+                    passes: tests - failures,
+                    failures,
+                },
+                source: None,
+                run: None,
+                tests: None,
+            };
+
+            let path = dir.join(format!("{}-{}.json", json.name, json.date));
+            fs::write(path, serde_json::to_string_pretty(&json).unwrap())
+                .expect("couldn't write result");
+        }
+    }
+
+    println!(
+        "wrote {} results to {}",
+        TESTSUITES as u64 * DAYS,
+        dir.display()
+    );
+}
+
+async fn get(client: &reqwest::Client, url: &str) -> Result<String, reqwest::Error> {
+    client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await
+}
+
+/// Build the list of URLs to request, covering every testsuite and date served
+async fn endpoints(client: &reqwest::Client, base: &str) -> Vec<String> {
+    let testsuites = get(client, &format!("{base}/api/testsuites"))
+        .await
+        .expect("couldn't list testsuites");
+    let testsuites: Vec<String> = serde_json::from_str(&testsuites).unwrap();
+
+    let dates = get(client, &format!("{base}/api/dates"))
+        .await
+        .expect("couldn't list dates");
+    let dates: Vec<String> = serde_json::from_str(&dates).unwrap();
+
+    let mut endpoints = vec![
+        format!("{base}/api/testsuites"),
+        format!("{base}/api/dates"),
+    ];
+    endpoints.extend(
+        testsuites
+            .iter()
+            .map(|key| format!("{base}/api/testsuites/{key}")),
+    );
+    endpoints.extend(dates.iter().map(|date| format!("{base}/api/runs/{date}")));
+    endpoints.extend(
+        testsuites
+            .iter()
+            .zip(dates.iter().cycle())
+            .map(|(key, date)| format!("{base}/api/testsuites/{key}/{date}")),
+    );
+
+    endpoints
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::from_args();
+    if let Some(dir) = &args.write_results {
+        write_results(dir);
+        return;
+    }
+
+    let client = reqwest::Client::new();
+
+    let endpoints = endpoints(&client, &args.url).await;
+
+    println!(
+        "{} clients, {} requests each, over {} endpoints",
+        args.clients,
+        args.requests,
+        endpoints.len()
+    );
+
+    let start = Instant::now();
+
+    let clients = (0..args.clients).map(|i| {
+        let client = client.clone();
+        let endpoints = endpoints.clone();
+        let requests = args.requests;
+
+        tokio::spawn(async move {
+            let mut latencies = Vec::with_capacity(requests);
+            let mut errors = 0;
+
+            for url in endpoints.iter().cycle().skip(i).take(requests) {
+                let start = Instant::now();
+                if get(&client, url).await.is_err() {
+                    errors += 1;
+                }
+                latencies.push(start.elapsed());
+            }
+
+            (latencies, errors)
+        })
+    });
+
+    let mut latencies: Vec<Duration> = vec![];
+    let mut errors = 0;
+    for client in clients.collect::<Vec<_>>() {
+        let (client_latencies, client_errors) = client.await.unwrap();
+        latencies.extend(client_latencies);
+        errors += client_errors;
+    }
+
+    let elapsed = start.elapsed();
+    latencies.sort();
+
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+
+    println!(
+        "{} requests ({} errors) in {:.2?}",
+        latencies.len(),
+        errors,
+        elapsed
+    );
+    println!(
+        "throughput: {:.0} requests/s",
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "latency: p50 {:.2?}, p99 {:.2?}, max {:.2?}",
+        percentile(50),
+        percentile(99),
+        percentile(100)
+    );
+}
@@ -0,0 +1,92 @@
+use hmac::{Hmac, Mac};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use sha2::Sha256;
+
+use crate::config::Uploader;
+use crate::error::ApiError;
+
+/// Uploaders allowed to push results, managed by Rocket
+pub struct Uploaders(pub Vec<Uploader>);
+
+/// Token giving access to the admin routes, managed by Rocket. If there is none,
+/// these routes are disabled
+pub struct AdminToken(pub Option<String>);
+
+fn bearer<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .filter(|token| !token.is_empty())
+}
+
+/// Check a token sent by a client against the one we expect. Both are used as the
+/// key of an HMAC, whose results are compared in constant time, so that neither
+/// the expected token nor its length can be guessed by timing responses
+fn matches(token: &str, expected: &str) -> bool {
+    let mac = |key: &str| {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+        mac.update(b"bottlecache");
+        mac
+    };
+
+    mac(token)
+        .verify_slice(&mac(expected).finalize().into_bytes())
+        .is_ok()
+}
+
+/// Request guard for routes restricted to uploaders. Succeeds if the request has
+/// an `Authorization: Bearer <token>` header matching one of the configured
+/// uploaders, and holds the name of that uploader
+pub struct Authenticated(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authenticated {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match bearer(request) {
+            Some(token) => token,
+            None => {
+                return Outcome::Error((Status::Unauthorized, ApiError::unauthorized()));
+            }
+        };
+
+        let uploader = request.rocket().state::<Uploaders>().and_then(|uploaders| {
+            uploaders
+                .0
+                .iter()
+                .find(|uploader| matches(token, &uploader.token))
+        });
+
+        match uploader {
+            Some(uploader) => Outcome::Success(Authenticated(uploader.name.clone())),
+            None => Outcome::Error((Status::Unauthorized, ApiError::unauthorized())),
+        }
+    }
+}
+
+/// Request guard for routes restricted to maintainers of the instance. Succeeds if
+/// the request has an `Authorization: Bearer <token>` header matching the admin
+/// token
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = request
+            .rocket()
+            .state::<AdminToken>()
+            .and_then(|token| token.0.as_deref());
+
+        match (bearer(request), expected) {
+            (Some(token), Some(expected)) if matches(token, expected) => Outcome::Success(Admin),
+            _ => Outcome::Error((Status::Unauthorized, ApiError::unauthorized())),
+        }
+    }
+}
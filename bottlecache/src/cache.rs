@@ -1,165 +1,931 @@
 mod artifact;
+mod conflict;
+mod index;
+mod snapshot;
+mod source;
+pub mod storage;
 
 use std::collections::HashSet;
-use std::path::{Path, PathBuf};
-use std::time::{SystemTime, SystemTimeError};
-use std::{fs, io};
-
-use chrono::Duration;
-use log::{debug, info, warn};
-use octocrab::models::RunId;
-use thiserror::Error;
-
-use common::TestsuiteResult;
-
-use self::artifact::Fetcher;
-
-#[derive(Debug, Error)]
-pub enum Error {
-    #[error("couldn't fetch file creation date")]
-    FileCreationDate(#[from] SystemTimeError),
-    #[error("error when using github API: {0}")]
-    GitHub(#[from] octocrab::Error),
-    #[error("error when extracting archive: {0}")]
-    Unzipping(#[from] zip::result::ZipError),
-    #[error("writing to disk failed: {0}")]
-    Disk(#[from] io::Error),
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::{Days, NaiveDate, Utc};
+use futures::stream::{self, StreamExt};
+use log::{debug, error, info, warn};
+use octocrab::models::{ArtifactId, RunId};
+use tokio::sync::Mutex;
+
+use common::{Conflict, TestsuiteResult};
+
+pub use self::artifact::{Credentials, LimitExceeded, Limits};
+pub use self::conflict::ConflictPolicy;
+use self::conflict::Resolution;
+use self::index::{IndexEntry, RunIndex};
+pub use self::snapshot::{run_order, DayPolicy, Snapshot, Snapshots};
+pub use self::source::{from_config, ResultSource};
+use self::source::{Batch, Item};
+use self::storage::{Contents, Rejected, Storage};
+use crate::config::Workflow;
+use crate::error::Error;
+use crate::notifier::Notifier;
+use crate::regression::{self, Thresholds};
+
+/// How far before the newest result of a source refreshes look for new items.
+/// Older items, e.g. runs which couldn't be fetched for more than a week, are left
+/// to `backfill`
+const REFRESH_WINDOW: Days = Days::new(7);
+
+/// How the cache deals with several results for the same testsuite
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Policies {
+    /// Which run of a testsuite is published as its result for a day
+    pub day_result: DayPolicy,
+    /// How results for a run which already has a different one are handled
+    pub conflicts: ConflictPolicy,
 }
 
 // FIXME: We probably want to keep the last variation in a cache type or something
 /// Cache for CI runs
 pub struct Cache {
-    /// Location of the cache on the disk
-    location: Option<PathBuf>,
-    fetcher: Fetcher,
-    last_date: SystemTime,
+    /// Where results are persisted, if anywhere
+    storage: Option<Box<dyn Storage>>,
+    /// Where to fetch testsuite results from
+    sources: Vec<Arc<dyn ResultSource>>,
+    /// How many items of a source are fetched at once
+    concurrency: usize,
     cached_data: HashSet<TestsuiteResult>,
-    cached_runs: HashSet<RunId>,
-    /// If we are in `mock` mode, then the cache never invalidates and never updates
-    /// It will always return what it currently contains. This implies that you
-    /// should give a valid location to this cache instance
-    mock: bool,
+    /// Items of the sources which were already ingested. This is persisted along
+    /// with the results so that it survives restarts
+    cached_runs: RunIndex,
+    /// Latest published view of `cached_data`, which is what requests are served from
+    snapshots: Snapshots,
+    /// Used to find regressions in the published results
+    thresholds: Thresholds,
+    policies: Policies,
+    /// Runs with several different results which the conflict policy left
+    /// unresolved
+    conflicts: Vec<Conflict>,
+    /// Notified of regressions in newly fetched results
+    notifier: Arc<Notifier>,
 }
 
 impl Cache {
-    fn fill_cache_from_dir(path: &Path) -> Result<HashSet<TestsuiteResult>, Error> {
-        // The cache is as restrictive as possible, so if a file is malformed or not completely
-        // valid JSON we just skip it
-        let existing_cache = fs::read_dir(path)?
-            .map(|entry| {
-                let path = entry?.path();
-                info!("reading from {}...", path.display());
-
-                fs::read_to_string(path)
-            })
-            .filter_map(Result::ok)
-            .map(|s| serde_json::from_str(&s))
-            .filter_map(Result::ok)
-            .collect::<HashSet<TestsuiteResult>>();
+    /// Create a cache, loading the results persisted in `storage`. Malformed entries
+    /// are quarantined, unless `strict` is set in which case they are an error
+    pub fn try_new(
+        mut storage: Option<Box<dyn Storage>>,
+        sources: Vec<Arc<dyn ResultSource>>,
+        concurrency: usize,
+        thresholds: Thresholds,
+        policies: Policies,
+        notifier: Notifier,
+        strict: bool,
+    ) -> Result<Cache, Error> {
+        let contents = match &mut storage {
+            Some(storage) => storage.load()?,
+            None => Contents::default(),
+        };
+
+        if !contents.rejected.is_empty() {
+            for rejected in &contents.rejected {
+                warn!("rejected {}: {}", rejected.entry, rejected.reason);
+            }
+
+            if strict {
+                return Err(Error::Malformed(contents.rejected.len()));
+            }
+
+            warn!(
+                "{} malformed entries found in the cache, moving them to quarantine",
+                contents.rejected.len()
+            );
+            if let Some(storage) = &mut storage {
+                storage.quarantine()?;
+            }
+        }
+
+        let (cached_data, conflicts) = policies.conflicts.detect(contents.results);
+        for conflict in &conflicts {
+            warn!(
+                "{} ({}): {} conflicting results for run {} of commit {}",
+                conflict.name,
+                conflict.date,
+                conflict.results.len(),
+                conflict.run.as_deref().unwrap_or("<unknown>"),
+                conflict.commit
+            );
+        }
+
+        let cache = Cache {
+            storage,
+            sources,
+            concurrency,
+            cached_data,
+            cached_runs: contents.index,
+            snapshots: Snapshots::default(),
+            thresholds,
+            policies,
+            conflicts,
+            notifier: Arc::new(notifier),
+        };
+        cache.publish(None);
+
+        Ok(cache)
+    }
 
-        Ok(existing_cache)
+    /// Persist a testsuite result, returning the path to the file it was written
+    /// to if the storage uses files
+    fn try_write(&mut self, json: &TestsuiteResult) -> Result<Option<PathBuf>, Error> {
+        match &mut self.storage {
+            Some(storage) => storage.write(json),
+            None => Ok(None),
+        }
     }
 
-    pub fn try_new(token: String, location: Option<PathBuf>, mock: bool) -> Result<Cache, Error> {
-        let cached_data = if let Some(path) = &location {
-            if path.exists() {
-                Cache::fill_cache_from_dir(path)?
-            } else {
-                fs::create_dir_all(path)?;
-                HashSet::new()
+    /// Check a new result against the result we already have for its run, if any.
+    /// Returns what to do with the new result: results for new runs are stored as
+    /// if they replaced one, results we already have are kept, and conflicting
+    /// ones are handled according to the conflict policy
+    fn accept(&mut self, json: &TestsuiteResult) -> Resolution {
+        let existing = match self.cached_data.iter().find(|cached| cached.same_run(json)) {
+            Some(existing) => existing.clone(),
+            None => return Resolution::Replace,
+        };
+
+        if !conflict::differ(&existing, json) {
+            info!(
+                "{} ({}) is already cached, skipping it",
+                json.name, json.date
+            );
+            return Resolution::Keep;
+        }
+
+        match self.policies.conflicts.resolve(&existing, json) {
+            Resolution::Keep => {
+                warn!(
+                    "conflicting results for {} ({}), keeping the one we already have",
+                    json.name, json.date
+                );
+                Resolution::Keep
+            }
+            Resolution::Replace => {
+                warn!(
+                    "conflicting results for {} ({}), replacing the one we already have",
+                    json.name, json.date
+                );
+                self.cached_data.remove(&existing);
+                Resolution::Replace
             }
+            Resolution::Unresolved => {
+                warn!(
+                    "conflicting results for {} ({}), keeping the one we already have until the conflict is resolved",
+                    json.name, json.date
+                );
+                conflict::record(&mut self.conflicts, &existing, json.clone());
+                Resolution::Unresolved
+            }
+        }
+    }
+
+    /// Store a testsuite result fetched from one of the sources. Returns whether
+    /// the result conflicts with one we already have and was left for maintainers
+    /// to resolve
+    fn store(
+        &mut self,
+        source: &str,
+        item: &Item,
+        artifact: Option<ArtifactId>,
+        mut json: TestsuiteResult,
+    ) -> Result<bool, Error> {
+        info!(
+            "valid json: {} ({}) from {}! Storing in cache",
+            json.name, json.date, source
+        );
+        json.source = Some(source.to_string());
+        // Results which don't say which run produced them are identified by the
+        // item they come from
+        json.run.get_or_insert_with(|| item.key.clone());
+
+        let resolution = self.accept(&json);
+        let file = if resolution == Resolution::Replace {
+            let file = self.try_write(&json)?;
+            self.cached_data.insert(json);
+            file
         } else {
-            HashSet::new()
+            None
         };
 
-        Ok(Cache {
-            location,
-            fetcher: Fetcher::try_new(token)?,
-            last_date: SystemTime::UNIX_EPOCH,
-            cached_data,
-            cached_runs: HashSet::new(),
-            mock,
-        })
+        self.index(item, artifact, file);
+
+        Ok(resolution == Resolution::Unresolved)
     }
 
-    fn is_invalidated(&self) -> Result<bool, Error> {
-        if self.mock {
-            Ok(false) // the cache is always valid
-        } else {
-            let now = SystemTime::now();
-            let age = now.duration_since(self.last_date)?;
+    /// Record that an item was ingested, so that it is never fetched again
+    fn index(&mut self, item: &Item, artifact: Option<ArtifactId>, file: Option<PathBuf>) {
+        self.cached_runs.insert(
+            item.key.clone(),
+            IndexEntry {
+                artifact,
+                downloaded: Utc::now(),
+                file: file.unwrap_or_default(),
+            },
+        );
+    }
 
-            // UNWRAP: If we have an issue here, this is a programmer error: We want
-            // the program to crash as this should never happen
-            Ok(age > Duration::hours(24).to_std().unwrap())
+    /// Store the testsuite results fetched from one of a source's items
+    fn ingest(&mut self, source: &str, item: &Item, batches: Vec<Batch>) -> Result<(), Error> {
+        // The item may have been fetched concurrently, e.g. by a refresh and when
+        // GitHub notified us of it
+        if self.cached_runs.contains(&item.key) {
+            info!("{}: {} is already cached, skipping it", source, item.key);
+            return Ok(());
         }
-    }
 
-    fn try_write(&self, json: &TestsuiteResult) -> Result<(), io::Error> {
-        match &self.location {
-            Some(path) => {
-                let path = path
-                    .join(format!("{}-{}", &json.name, json.date.format("%Y-%m-%d")))
-                    .with_extension("json");
-                fs::write(path, serde_json::to_string_pretty(json)?)
+        // Items without any valid result are indexed as well, so that they are not
+        // fetched again on every refresh
+        if !batches
+            .iter()
+            .flat_map(|batch| &batch.entries)
+            .any(|entry| {
+                entry
+                    .results
+                    .as_ref()
+                    .is_ok_and(|results| !results.is_empty())
+            })
+        {
+            warn!("{}: no results in {}", source, item.key);
+
+            if batches.is_empty() {
+                self.index(item, None, None);
+            }
+            for batch in &batches {
+                self.index(item, batch.artifact, None);
             }
+        }
+
+        let mut unresolved = false;
+        for batch in batches {
+            for entry in batch.entries {
+                match entry.results {
+                    Ok(results) => {
+                        for json in results {
+                            unresolved |= self.store(source, item, batch.artifact, json)?;
+                        }
+                    }
+                    Err(e) => warn!(
+                        "{}: invalid file `{}` in {}... skipping it. Reason: `{}`",
+                        source, entry.name, item.key, e
+                    ),
+                }
+            }
+        }
+
+        // Unresolved conflicts are only kept in memory, so their items are fetched
+        // again on every refresh and after a restart, until the conflict is resolved
+        if unresolved {
+            self.cached_runs.remove(&item.key);
+        }
+
+        match &mut self.storage {
+            Some(storage) => storage.store_index(&self.cached_runs),
             None => Ok(()),
         }
     }
 
-    async fn update(&mut self) -> Result<(), Error> {
-        let runs = self.fetcher.runs().await?;
-        let runs: Vec<RunId> = runs
-            .into_iter()
-            .filter(|run| !self.cached_runs.contains(run))
-            .collect();
+    /// Fetch items of a source concurrently, storing their results as they come
+    /// in. The cache is only locked while results are stored, so that it can be
+    /// used while we wait on the source. Items which can't be fetched are skipped:
+    /// they are not indexed, so they are fetched again on the next refresh. The last
+    /// error is returned
+    async fn fetch_items(
+        cache: &Mutex<Cache>,
+        source: Arc<dyn ResultSource>,
+        items: Vec<Item>,
+    ) -> Result<(), Error> {
+        let name = source.name();
+        let total = items.len();
+        let concurrency = cache.lock().await.concurrency;
+        let mut fetched = stream::iter(items)
+            .map(|item| {
+                let source = source.clone();
 
-        debug!("{:#?}", runs);
+                async move {
+                    let batches = source.fetch(&item).await;
+                    (item, batches)
+                }
+            })
+            .buffered(concurrency);
+        let mut result = Ok(());
+        let mut n = 0;
 
-        let archives = self.fetcher.result_files(&runs).await?;
+        while let Some((item, batches)) = fetched.next().await {
+            n += 1;
 
-        for (run, archive) in archives {
-            let bytes = artifact::extract_json(archive)?;
+            match batches {
+                Ok(batches) => {
+                    info!("{}: [{}/{}] fetched {}", name, n, total, item.key);
+                    cache.lock().await.ingest(&name, &item, batches)?;
+                }
+                // Every other request would fail as well
+                Err(e @ Error::RateLimited(_)) => return Err(e),
+                Err(e) => {
+                    error!(
+                        "{}: [{}/{}] couldn't fetch {}: {}",
+                        name, n, total, item.key, e
+                    );
+                    result = Err(e);
+                }
+            }
+        }
 
-            debug!("{}", String::from_utf8_lossy(&bytes));
+        result
+    }
 
-            let json = TestsuiteResult::from_bytes(bytes.as_slice());
+    /// Fetch and store the items of a source which were not ingested yet
+    async fn update_source(
+        cache: &Mutex<Cache>,
+        source: Arc<dyn ResultSource>,
+    ) -> Result<(), Error> {
+        let since = cache.lock().await.refresh_since(&source.name());
+        let items = source.list(Some(since)).await?;
+        let items: Vec<Item> = {
+            let cache = cache.lock().await;
+            items
+                .into_iter()
+                .filter(|item| !cache.cached_runs.contains(&item.key))
+                .collect()
+        };
 
-            match json {
-                Ok(json) => {
-                    info!(
-                        "valid json: {} ({})! Storing in cache",
-                        json.name, json.date
-                    );
-                    self.try_write(&json)?;
+        debug!("{}: {:#?}", source.name(), items);
+
+        Cache::fetch_items(cache, source, items).await
+    }
+
+    /// Store a result pushed by an uploader and publish it right away. Results for
+    /// a run which already has one, i.e. with the same testsuite, date, commit and
+    /// run id, are rejected unless the conflict policy says to replace it. Returns
+    /// the result as it was stored
+    pub async fn push(
+        cache: &Mutex<Cache>,
+        uploader: &str,
+        json: TestsuiteResult,
+    ) -> Result<TestsuiteResult, Error> {
+        cache.lock().await.store_pushed(uploader, json)
+    }
+
+    /// Store a result pushed by an uploader, returning it as it was stored
+    fn store_pushed(
+        &mut self,
+        uploader: &str,
+        mut json: TestsuiteResult,
+    ) -> Result<TestsuiteResult, Error> {
+        json.source = Some(format!("upload/{uploader}"));
 
-                    self.cached_data.insert(json);
-                    self.cached_runs.insert(run);
+        if let Some(cached) = self
+            .cached_data
+            .iter()
+            .find(|cached| cached.same_run(&json))
+            .cloned()
+        {
+            // Uploaders may retry a request whose response they never got
+            if !conflict::differ(&cached, &json) {
+                return Err(Error::Duplicate(json.name, json.date));
+            }
+
+            // Uploaders are told about conflicts right away, so they are never
+            // left for maintainers to resolve
+            match self.policies.conflicts.resolve(&cached, &json) {
+                Resolution::Replace => {
+                    warn!(
+                        "{} ({}) pushed by {} replaces the result we already have",
+                        json.name, json.date, uploader
+                    );
+                    self.cached_data.remove(&cached);
+                }
+                Resolution::Keep | Resolution::Unresolved => {
+                    return Err(Error::Conflict(json.name, json.date));
                 }
-                Err(e) => warn!(
-                    "invalid json file downloaded from github... skipping it. Reason: `{}`",
-                    e
-                ),
             }
         }
 
-        self.last_date = SystemTime::now();
+        info!(
+            "{} ({}) pushed by {}! Storing in cache",
+            json.name, json.date, uploader
+        );
+        self.try_write(&json)?;
+        self.cached_data.insert(json.clone());
+
+        // Keep flagging the snapshot as stale if the last refresh failed
+        self.publish(self.snapshots.load().error.clone());
+
+        Ok(json)
+    }
+
+    /// Fetch the results of a single run of a GitHub workflow, e.g. when GitHub
+    /// notifies us that it completed, and publish them right away
+    pub async fn fetch_run(
+        cache: &Mutex<Cache>,
+        workflow: &Workflow,
+        run: RunId,
+    ) -> Result<(), Error> {
+        let item = Item {
+            key: run.to_string(),
+            date: None,
+        };
+
+        let source = {
+            let cache = cache.lock().await;
+
+            let source = cache
+                .sources
+                .iter()
+                .find(|source| source.workflow() == Some(workflow));
+            match source {
+                Some(_) if cache.cached_runs.contains(&item.key) => {
+                    info!("{}: run {} is already cached, skipping it", workflow, run);
+                    return Ok(());
+                }
+                Some(source) => source.clone(),
+                None => {
+                    warn!("{}: not a source, ignoring run {}", workflow, run);
+                    return Ok(());
+                }
+            }
+        };
+
+        info!("{}: fetching run {}", workflow, run);
+
+        let batches = source.fetch(&item).await?;
+
+        let mut cache = cache.lock().await;
+        cache.ingest(&workflow.to_string(), &item, batches)?;
+        cache.publish(cache.snapshots.load().error.clone());
 
         Ok(())
     }
 
-    pub async fn data(&mut self) -> Result<HashSet<TestsuiteResult>, Error> {
-        if self.is_invalidated()? {
-            info!("updating cache");
-            self.update().await?;
-        } else {
-            info!("return cached data!");
+    /// Oldest day a refresh looks for new items of a source on: a week before its
+    /// newest result, or before today if we have none
+    fn refresh_since(&self, source: &str) -> NaiveDate {
+        let newest = self
+            .cached_data
+            .iter()
+            .filter(|json| json.source.as_deref() == Some(source))
+            .map(|json| json.date)
+            .max()
+            .unwrap_or_else(|| Utc::now().date_naive());
+
+        newest - REFRESH_WINDOW
+    }
+
+    /// Is there already a result on disk for that item of a source? Results which
+    /// predate source tracking can only be attributed to a source if it is the
+    /// only one configured, and results which predate run tracking to an item
+    /// produced on the same day
+    fn has_result(&self, source: &str, item: &Item) -> bool {
+        let single = self.sources.len() == 1;
+
+        self.cached_data.iter().any(|json| {
+            let from_source = match &json.source {
+                Some(s) => s == source,
+                None => single,
+            };
+            let from_item = match &json.run {
+                Some(run) => *run == item.key,
+                None => item.date == Some(json.date),
+            };
+
+            from_source && from_item
+        })
+    }
+
+    /// Fetch and store every result produced between `since` and `until`, both
+    /// inclusive, skipping items for which results are already cached. Items whose
+    /// date is only known once fetched, such as files, are always fetched. Items
+    /// which can't be fetched are skipped, and the last error is returned
+    pub async fn backfill(
+        cache: &Mutex<Cache>,
+        since: NaiveDate,
+        until: NaiveDate,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+        let mut result = Ok(());
+        let sources = cache.lock().await.sources.clone();
+
+        for source in sources {
+            let name = source.name();
+            info!("{}: listing items between {} and {}", name, since, until);
+
+            let items = source.list(Some(since)).await?;
+            let items: Vec<_> = {
+                let cache = cache.lock().await;
+                items
+                    .into_iter()
+                    .filter(|item| item.date.is_none_or(|date| since <= date && date <= until))
+                    .filter(|item| {
+                        let cached =
+                            cache.cached_runs.contains(&item.key) || cache.has_result(&name, item);
+                        if cached {
+                            info!("{}: {} is already cached, skipping it", name, item.key);
+                        }
+
+                        !cached
+                    })
+                    .collect()
+            };
+
+            // Backfilled results are history: we don't notify anyone about them
+            match Cache::fetch_items(cache, source, items).await {
+                Err(e @ Error::RateLimited(_)) => return Err(e),
+                Err(e) => result = Err(e),
+                Ok(()) => {}
+            }
+        }
+
+        info!("backfilled cache in {:.1?}", start.elapsed());
+
+        result
+    }
+
+    /// Fetch the results of every new run from the cache's sources. If this fails,
+    /// the published snapshot keeps the data we already had and is marked as stale.
+    /// The cache is only locked while new results are stored, never while we wait
+    /// on the sources
+    pub async fn update(cache: &Mutex<Cache>) -> Result<(), Arc<Error>> {
+        info!("updating cache");
+
+        let start = Instant::now();
+        let result = Cache::update_all(cache).await.map_err(Arc::new);
+
+        let cache = cache.lock().await;
+        info!(
+            "synced {} sources in {:.1?}",
+            cache.sources.len(),
+            start.elapsed()
+        );
+
+        // Even if one of the sources failed, publish what we got from the others
+        cache.publish(result.as_ref().err().cloned());
+
+        result
+    }
+
+    async fn update_all(cache: &Mutex<Cache>) -> Result<(), Error> {
+        let mut result = Ok(());
+        let sources = cache.lock().await.sources.clone();
+
+        // A source failing doesn't prevent the others from being fetched
+        for source in sources {
+            let name = source.name();
+            if let Err(e) = Cache::update_source(cache, source).await {
+                error!("{}: couldn't update: {}", name, e);
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    /// Every malformed entry which was ever moved out of the storage
+    pub fn quarantined(&mut self) -> Result<Vec<Rejected>, Error> {
+        match &mut self.storage {
+            Some(storage) => storage.quarantined(),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Handle to the snapshots published by this cache
+    pub fn snapshots(&self) -> Snapshots {
+        self.snapshots.clone()
+    }
+
+    /// Publish a snapshot of the results, and notify webhooks of the regressions
+    /// which it is the first to contain. Regressions are only found once every new
+    /// result is stored, since results don't come in in order. Nothing is notified
+    /// when nothing was published before, so that new deployments don't notify every
+    /// regression in the history of their sources
+    fn publish(&self, error: Option<Arc<Error>>) {
+        let previous = self.snapshots.load();
+        let results: HashSet<_> = self
+            .policies
+            .day_result
+            .select(self.cached_data.iter())
+            .cloned()
+            .collect();
+        let regressions = regression::analyze(results.iter(), &self.thresholds);
+
+        let new: Vec<_> = regressions
+            .iter()
+            .filter(|regression| !previous.regressions.contains(regression))
+            .cloned()
+            .collect();
+        if !previous.results.is_empty() && !new.is_empty() {
+            for regression in &new {
+                warn!("regression in {} ({})", regression.name, regression.to);
+            }
+
+            // Webhooks may be slow to answer, and are notified in the background
+            let notifier = self.notifier.clone();
+            tokio::spawn(async move { notifier.notify(&new).await });
+        }
+
+        self.snapshots.publish(Snapshot {
+            regressions,
+            results,
+            runs: self.cached_data.clone(),
+            conflicts: self.conflicts.clone(),
+            error,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use common::Regression;
+    use tokio::sync::mpsc;
+
+    use self::artifact::Entry;
+    use self::source::ResultSource;
+
+    use crate::config::Backend;
+    use crate::notifier::{Format, NotifierConfig, Webhook};
+    use crate::stand_in::{self, Response};
+
+    /// Source whose items are files, each holding the given contents
+    struct Files {
+        files: Vec<(&'static str, &'static str)>,
+        fetched: AtomicUsize,
+        /// The `since` given to every call to `list`
+        listed: std::sync::Mutex<Vec<Option<NaiveDate>>>,
+    }
+
+    impl Files {
+        fn new(files: Vec<(&'static str, &'static str)>) -> Arc<Files> {
+            Arc::new(Files {
+                files,
+                fetched: AtomicUsize::new(0),
+                listed: std::sync::Mutex::default(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl ResultSource for Files {
+        fn name(&self) -> String {
+            String::from("files")
         }
 
-        // UNWRAP: We can safely unwrap here as our data is updated. If there is
-        // no data or none has been fetched/written to disk, then the `update`
-        // function will have returned an error which would have been propagated
-        // already.
-        Ok(self.cached_data.clone())
+        async fn list(&self, since: Option<NaiveDate>) -> Result<Vec<Item>, Error> {
+            self.listed.lock().unwrap().push(since);
+
+            Ok(self
+                .files
+                .iter()
+                .map(|(name, _)| Item {
+                    key: name.to_string(),
+                    date: None,
+                })
+                .collect())
+        }
+
+        async fn fetch(&self, item: &Item) -> Result<Vec<Batch>, Error> {
+            self.fetched.fetch_add(1, Ordering::SeqCst);
+
+            Ok(self
+                .files
+                .iter()
+                .filter(|(name, _)| *name == item.key)
+                .map(|(name, contents)| Batch {
+                    artifact: None,
+                    entries: vec![Entry {
+                        name: name.to_string(),
+                        results: TestsuiteResult::all_from_bytes(contents.as_bytes())
+                            .map_err(Into::into),
+                    }],
+                })
+                .collect())
+        }
+    }
+
+    fn cache(sources: Vec<Arc<dyn ResultSource>>) -> Cache {
+        Cache::try_new(
+            None,
+            sources,
+            1,
+            Thresholds::default(),
+            Policies::default(),
+            Notifier::try_new(NotifierConfig::default(), None).unwrap(),
+            false,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn indexes_items_without_results() {
+        let source = Files::new(vec![
+            ("invalid.json", "{ \"name\": "),
+            ("empty.json", "[]"),
+            ("valid.json", include_str!("../mock/valid0.json")),
+        ]);
+        let cache = Mutex::new(cache(vec![source.clone()]));
+
+        Cache::update(&cache).await.unwrap();
+        assert_eq!(source.fetched.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.lock().await.cached_runs.iter().count(), 3);
+        assert_eq!(cache.lock().await.cached_data.len(), 1);
+
+        // None of them is fetched again
+        Cache::update(&cache).await.unwrap();
+        assert_eq!(source.fetched.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn matches_cached_results_to_their_run() {
+        let source = Files::new(vec![]);
+        let mut cache = cache(vec![source]);
+        cache.cached_data.insert(TestsuiteResult {
+            source: Some(String::from("files")),
+            run: Some(String::from("1")),
+            ..crate::fixtures::result(8, 2)
+        });
+        let item = |key: &str| Item {
+            key: key.to_string(),
+            date: Some(crate::fixtures::day(22)),
+        };
+
+        assert!(cache.has_result("files", &item("1")));
+        // Another run of the same day
+        assert!(!cache.has_result("files", &item("2")));
+        assert!(!cache.has_result("other", &item("1")));
+
+        // Results which predate run tracking are matched on their date
+        cache.cached_data.insert(crate::fixtures::result(8, 2));
+        assert!(cache.has_result("files", &item("2")));
+    }
+
+    #[tokio::test]
+    async fn only_refreshes_recent_items() {
+        let source = Files::new(vec![("valid.json", include_str!("../mock/valid0.json"))]);
+        let cache = Mutex::new(cache(vec![source.clone()]));
+
+        Cache::update(&cache).await.unwrap();
+        Cache::update(&cache).await.unwrap();
+
+        // Once we have a result, refreshes look for items from the week before it
+        assert_eq!(
+            *source.listed.lock().unwrap(),
+            [
+                Some(Utc::now().date_naive() - REFRESH_WINDOW),
+                NaiveDate::from_ymd_opt(2021, 7, 20),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_items_with_unresolved_conflicts_again() {
+        let source = Files::new(vec![
+            (
+                "arm.json",
+                r#"{ "name": "mock", "commit": "5eadad681a", "date": "2021-07-27", "run": "1",
+                     "results": { "tests": 10, "passes": 8, "failures": 2 } }"#,
+            ),
+            (
+                "x86.json",
+                r#"{ "name": "mock", "commit": "5eadad681a", "date": "2021-07-27", "run": "1",
+                     "results": { "tests": 10, "passes": 6, "failures": 4 } }"#,
+            ),
+        ]);
+        let cache = Mutex::new(cache(vec![source.clone()]));
+
+        Cache::update(&cache).await.unwrap();
+        {
+            let cache = cache.lock().await;
+            assert_eq!(cache.conflicts.len(), 1);
+            assert!(cache.cached_runs.contains("arm.json"));
+            assert!(!cache.cached_runs.contains("x86.json"));
+        }
+
+        // The conflicting item is fetched again, without listing its conflict twice
+        Cache::update(&cache).await.unwrap();
+        assert_eq!(source.fetched.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.lock().await.conflicts[0].results.len(), 2);
+    }
+
+    #[test]
+    fn refuses_malformed_entries_when_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("valid.json"),
+            include_str!("../mock/valid0.json"),
+        )
+        .unwrap();
+        fs::write(dir.path().join("invalid.json"), "{ \"name\": ").unwrap();
+        let storage = storage::open(Backend::Json, dir.path()).unwrap();
+
+        let cache = Cache::try_new(
+            Some(storage),
+            vec![],
+            1,
+            Thresholds::default(),
+            Policies::default(),
+            Notifier::try_new(NotifierConfig::default(), None).unwrap(),
+            true,
+        );
+
+        assert!(matches!(cache, Err(Error::Malformed(1))));
+        // Nothing was quarantined
+        assert!(dir.path().join("invalid.json").exists());
+    }
+
+    /// Results of the `mock` testsuite on the first days of July 2021
+    const DAYS: [&str; 3] = [
+        r#"{ "name": "mock", "commit": "1", "date": "2021-07-01", "results": { "tests": 10, "passes": 8, "failures": 2 } }"#,
+        r#"{ "name": "mock", "commit": "2", "date": "2021-07-02", "results": { "tests": 10, "passes": 10, "failures": 0 } }"#,
+        r#"{ "name": "mock", "commit": "3", "date": "2021-07-03", "results": { "tests": 10, "passes": 8, "failures": 2 } }"#,
+    ];
+
+    /// Cache notifying a webhook, along with the notifications the webhook gets
+    async fn notifying(
+        sources: Vec<Arc<dyn ResultSource>>,
+    ) -> (Cache, mpsc::UnboundedReceiver<Regression>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let url = stand_in::serve_requests(move |request| {
+            tx.send(serde_json::from_slice(&request.body).unwrap())
+                .unwrap();
+            Response::new(200, "")
+        })
+        .await;
+        let config = NotifierConfig {
+            webhooks: vec![Webhook {
+                url,
+                format: Format::Json,
+                template: None,
+            }],
+            ..NotifierConfig::default()
+        };
+
+        let cache = Cache::try_new(
+            None,
+            sources,
+            1,
+            Thresholds::default(),
+            Policies::default(),
+            Notifier::try_new(config, None).unwrap(),
+            false,
+        )
+        .unwrap();
+
+        (cache, rx)
+    }
+
+    /// Wait a bit for notifications sent in the background
+    async fn no_notification(notifications: &mut mpsc::UnboundedReceiver<Regression>) -> bool {
+        tokio::time::timeout(Duration::from_millis(200), notifications.recv())
+            .await
+            .is_err()
+    }
+
+    #[tokio::test]
+    async fn notifies_regressions_between_new_results() {
+        // Newest first, like GitHub lists runs
+        let source = Files::new(vec![("3.json", DAYS[2]), ("2.json", DAYS[1])]);
+        let (mut cache, mut notifications) = notifying(vec![source]).await;
+        cache
+            .cached_data
+            .insert(serde_json::from_str(DAYS[0]).unwrap());
+        cache.publish(None);
+        let cache = Mutex::new(cache);
+
+        Cache::update(&cache).await.unwrap();
+
+        let regression = notifications.recv().await.unwrap();
+        assert_eq!(
+            (
+                regression.from_commit.as_str(),
+                regression.to_commit.as_str()
+            ),
+            ("2", "3")
+        );
+        assert!(no_notification(&mut notifications).await);
+    }
+
+    #[tokio::test]
+    async fn does_not_notify_history_on_first_sync() {
+        let source = Files::new(vec![("3.json", DAYS[2]), ("2.json", DAYS[1])]);
+        let (cache, mut notifications) = notifying(vec![source]).await;
+        let cache = Mutex::new(cache);
+
+        Cache::update(&cache).await.unwrap();
+
+        assert_eq!(cache.lock().await.snapshots.load().regressions.len(), 1);
+        assert!(no_notification(&mut notifications).await);
     }
 }
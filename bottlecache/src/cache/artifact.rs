@@ -1,94 +1,922 @@
 use std::{
-    io::{BufReader, Cursor},
+    future::Future,
+    io::{self, BufReader, Cursor, Read},
     path::Path,
+    sync::Arc,
+    time::Duration,
 };
 
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::future;
+use hyper::body::HttpBody;
+use hyper::header::RETRY_AFTER;
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use jsonwebtoken::EncodingKey;
 use octocrab::{
-    actions::ActionsHandler,
-    models::{ArtifactId, RunId},
-    params::actions::ArchiveFormat,
-    Octocrab, OctocrabBuilder,
+    models::{
+        workflows::{Run, WorkflowListArtifact},
+        AppId, ArtifactId, InstallationId, RunId,
+    },
+    FromResponse, Octocrab, OctocrabBuilder, Page,
 };
 
-// FIXME: Add proper Artifact structure?
-// FIXME: Is that type even needed?
+use log::warn;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::sync::Semaphore;
+
+use common::TestsuiteResult;
+
+use crate::config::{GitHubApp, Workflow};
+use crate::error::Error;
+
+/// A zipped artifact downloaded from GitHub
+#[derive(Debug)]
+pub struct Archive {
+    pub artifact: ArtifactId,
+    bytes: Vec<u8>,
+}
+
+/// Why a file of an archive could not be used
+#[derive(Debug, Error)]
+pub enum EntryError {
+    #[error("couldn't decompress file: {0}")]
+    Read(#[from] io::Error),
+    #[error("invalid testsuite results: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("file is too large: {0}")]
+    Limit(#[from] LimitExceeded),
+}
+
+/// A JSON file of an archive, along with the testsuite results it contains
+#[derive(Debug)]
+pub struct Entry {
+    pub name: String,
+    pub results: Result<Vec<TestsuiteResult>, EntryError>,
+}
+
+/// Bounds on the artifacts we are willing to download and extract, so that a
+/// malformed or malicious artifact cannot exhaust the server's memory
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Limits {
+    /// Maximum size of an archive, in bytes
+    pub compressed: u64,
+    /// Maximum size of the files of an archive once decompressed, in bytes
+    pub decompressed: u64,
+    /// Maximum number of files in an archive
+    pub entries: usize,
+    /// Maximum ratio between the decompressed and compressed sizes of an archive
+    pub ratio: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            compressed: 64 * 1024 * 1024,
+            decompressed: 256 * 1024 * 1024,
+            entries: 1024,
+            ratio: 100,
+        }
+    }
+}
+
+/// Which of the `Limits` an archive exceeds
+#[derive(Debug, Error)]
+pub enum LimitExceeded {
+    #[error("archive is {0} bytes, more than the limit of {1}")]
+    Compressed(u64, u64),
+    #[error("archive is larger than the limit of {0} bytes")]
+    Download(u64),
+    #[error("archive contains {0} files, more than the limit of {1}")]
+    Entries(usize, usize),
+    #[error("archive decompresses to more than {0} bytes")]
+    Decompressed(u64),
+    #[error("archive decompresses to more than {0} times its size")]
+    Ratio(u64),
+}
+
+/// How to authenticate to GitHub
 #[derive(Debug)]
-pub struct Archive(Vec<u8>);
+pub enum Credentials {
+    /// Personal access token
+    Token(String),
+    /// Installation of a GitHub App, for organizations which disallow personal
+    /// access tokens
+    App(GitHubApp),
+}
 
+/// Requests of the rate limit which we leave to other tools sharing our token
+const RATE_LIMIT_RESERVE: usize = 20;
+
+/// Longest we are willing to wait for the rate limit to be reset. Past that, the
+/// fetch is deferred to the next refresh
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(5 * 60);
+
+/// How many times requests failing because of GitHub or the network are retried
+const RETRIES: u32 = 4;
+
+/// Delay before the first retry of a request. It doubles on every retry
+const BACKOFF: Duration = Duration::from_secs(1);
+
+/// Is a request GitHub answered this way likely to succeed if it is retried?
+/// GitHub tells us when to retry requests hitting its secondary rate limits
+fn is_transient<B>(response: &Response<B>) -> bool {
+    let status = response.status();
+
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::FORBIDDEN && response.headers().contains_key(RETRY_AFTER))
+}
+
+/// Delay before the `attempt`th retry of a request, counting from 0
+fn backoff(attempt: u32) -> Duration {
+    BACKOFF * 2u32.pow(attempt)
+}
+
+/// Our rate limit, as reported by the headers of GitHub's last response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RateLimit {
+    remaining: usize,
+    reset: DateTime<Utc>,
+}
+
+impl RateLimit {
+    /// Responses which don't count against the rate limit, such as the archives
+    /// of artifacts, have no rate limit headers
+    fn from_headers(headers: &HeaderMap) -> Option<RateLimit> {
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.parse::<i64>().ok();
+
+        Some(RateLimit {
+            remaining: header("x-ratelimit-remaining")?.try_into().ok()?,
+            reset: DateTime::<Utc>::from_timestamp(header("x-ratelimit-reset")?, 0)?,
+        })
+    }
+}
+
+#[derive(Clone)]
 pub struct Fetcher {
     instance: Octocrab,
+    limits: Limits,
+    /// Bounds the number of artifacts downloaded at once, shared by every clone of
+    /// the fetcher
+    downloads: Arc<Semaphore>,
+    /// Unknown until GitHub answers our first request. Shared by every clone of
+    /// the fetcher, since they all use the same token
+    rate_limit: Arc<std::sync::Mutex<Option<RateLimit>>>,
 }
 
-// FIXME: Add documentation
-async fn download_artifact(
-    instance: &ActionsHandler<'_>,
-    artifact: ArtifactId,
-) -> Result<Archive, octocrab::Error> {
-    let archive = instance
-        .download_artifact("rust-gcc", "testing", artifact, ArchiveFormat::Zip)
-        .await?;
+/// Read a response's body, giving up as soon as it exceeds `limit` bytes instead
+/// of buffering all of it
+async fn read_limited(mut body: hyper::Body, limit: u64) -> Result<Vec<u8>, Error> {
+    let mut bytes = vec![];
+
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk?);
 
-    Ok(Archive(archive.to_vec()))
+        if bytes.len() as u64 > limit {
+            return Err(LimitExceeded::Download(limit).into());
+        }
+    }
+
+    Ok(bytes)
 }
 
 impl Fetcher {
-    pub fn try_new(access_token: String) -> Result<Fetcher, octocrab::Error> {
+    pub fn try_new(
+        credentials: Credentials,
+        limits: Limits,
+        concurrency: usize,
+    ) -> Result<Fetcher, Error> {
         let builder = OctocrabBuilder::new();
-        let builder = builder.personal_token(access_token);
 
-        let instance = builder.build()?;
+        let instance = match credentials {
+            Credentials::Token(token) => builder.personal_token(token).build()?,
+            // octocrab takes care of requesting installation tokens, and of renewing
+            // them once they expire
+            Credentials::App(app) => {
+                let key = EncodingKey::from_rsa_pem(&std::fs::read(&app.private_key)?)?;
+
+                builder
+                    .app(AppId(app.app_id), key)
+                    .build()?
+                    .installation(InstallationId(app.installation_id))
+            }
+        };
+
+        Ok(Fetcher::new(instance, limits, concurrency))
+    }
+
+    fn new(instance: Octocrab, limits: Limits, concurrency: usize) -> Fetcher {
+        Fetcher {
+            instance,
+            limits,
+            downloads: Arc::new(Semaphore::new(concurrency)),
+            rate_limit: Arc::default(),
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Make sure we can send a few more requests to GitHub. If the rate limit is
+    /// almost exhausted, wait until it is reset, or give up if that takes too long
+    async fn wait_for_rate_limit(&self) -> Result<(), Error> {
+        let rate = match *self.rate_limit.lock().unwrap() {
+            Some(rate) if rate.remaining <= RATE_LIMIT_RESERVE => rate,
+            _ => return Ok(()),
+        };
 
-        Ok(Fetcher { instance })
+        let wait = (rate.reset - Utc::now()).to_std().unwrap_or_default();
+        if wait > MAX_RATE_LIMIT_WAIT {
+            return Err(Error::RateLimited(rate.reset));
+        }
+
+        warn!(
+            "only {} requests left before reaching the rate limit, waiting {:?} for it to be reset",
+            rate.remaining, wait
+        );
+        // Leave GitHub some leeway to actually reset it
+        tokio::time::sleep(wait + BACKOFF).await;
+        // Whoever waited first finds out about the new rate limit with their request
+        *self.rate_limit.lock().unwrap() = None;
+
+        Ok(())
+    }
+
+    /// Send a GET request to GitHub, keeping track of the rate limit it reports
+    async fn get(&self, uri: &str) -> Result<Response<Body>, octocrab::Error> {
+        let response = self.instance._get(uri).await?;
+
+        if let Some(rate) = RateLimit::from_headers(response.headers()) {
+            *self.rate_limit.lock().unwrap() = Some(rate);
+        }
+
+        Ok(response)
+    }
+
+    /// Send a request, retrying it with an exponential backoff if it fails for a
+    /// transient reason, and turn GitHub's error responses into errors
+    async fn retry<F, Fut>(&self, what: &str, request: F) -> Result<Response<Body>, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Response<Body>, octocrab::Error>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            self.wait_for_rate_limit().await?;
+
+            let reason = match request().await {
+                Ok(response) if attempt < RETRIES && is_transient(&response) => {
+                    response.status().to_string()
+                }
+                // The network is the only other source of transient errors
+                Err(e @ (octocrab::Error::Hyper { .. } | octocrab::Error::Service { .. }))
+                    if attempt < RETRIES =>
+                {
+                    // octocrab appends a backtrace to its errors
+                    e.to_string().lines().next().unwrap_or_default().to_string()
+                }
+                Ok(response) => return Ok(octocrab::map_github_error(response).await?),
+                Err(e) => return Err(e.into()),
+            };
+
+            let delay = backoff(attempt);
+            warn!("couldn't {}: {}. Retrying in {:?}", what, reason, delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
     }
 
-    // FIXME: Add doc
-    pub async fn runs(&self) -> Result<Vec<RunId>, octocrab::Error> {
-        let page = self
-            .instance
-            .workflows("rust-gcc", "testing")
-            .list_runs("nightly_run.yml")
-            .send()
+    /// Fetch the runs of a source's workflow, newest first, walking through every
+    /// page of results. If `since` is given, runs created before that date are
+    /// not returned and no further pages are requested once they are reached.
+    pub async fn runs(
+        &self,
+        source: &Workflow,
+        since: Option<NaiveDate>,
+    ) -> Result<Vec<Run>, Error> {
+        let is_recent_enough =
+            |run: &Run| since.is_none_or(|since| run.created_at.date_naive() >= since);
+
+        let route = format!(
+            "/repos/{}/{}/actions/workflows/{}/runs?per_page=100",
+            source.owner, source.repo, source.workflow
+        );
+        let response = self.retry("list runs", || self.get(&route)).await?;
+        let mut page = Page::<Run>::from_response(response).await?;
+        let mut runs = vec![];
+
+        loop {
+            let items = page.take_items();
+            let exhausted = !items.iter().all(is_recent_enough);
+
+            runs.extend(items.into_iter().filter(is_recent_enough));
+
+            if exhausted {
+                break;
+            }
+
+            let next = match &page.next {
+                Some(next) => next.to_string(),
+                None => break,
+            };
+            let response = self.retry("list runs", || self.get(&next)).await?;
+            page = Page::from_response(response).await?;
+        }
+
+        Ok(runs)
+    }
+
+    /// Download an artifact of a source's workflow. The size GitHub reports for
+    /// artifacts can't be trusted, so the download is aborted as soon as it exceeds
+    /// the compressed size limit
+    async fn download(&self, source: &Workflow, artifact: ArtifactId) -> Result<Archive, Error> {
+        let route = format!(
+            "/repos/{}/{}/actions/artifacts/{}/zip",
+            source.owner, source.repo, artifact
+        );
+
+        let response = self
+            .retry("download artifact", || async {
+                let response = self.get(&route).await?;
+                // GitHub redirects us to the archive itself
+                self.instance.follow_location_to_data(response).await
+            })
             .await?;
 
-        Ok(page.into_iter().map(|run| run.id).collect())
+        Ok(Archive {
+            artifact,
+            bytes: read_limited(response.into_body(), self.limits.compressed).await?,
+        })
     }
 
-    // FIXME: Add doc
-    // FIXME: Return the actual files
+    /// Download the archives of the given runs which contain JSON files, along
+    /// with the run each of them belongs to. Archives are downloaded concurrently,
+    /// but never more than the fetcher's concurrency limit at once
     pub async fn result_files(
         &self,
+        source: &Workflow,
         runs: &[RunId],
-    ) -> Result<Vec<(RunId, Archive)>, octocrab::Error> {
-        let actions = self.instance.actions();
-        let mut archives = vec![];
+    ) -> Result<Vec<(RunId, Archive)>, Error> {
+        let mut artifacts = vec![];
 
         for run in runs {
-            let list = actions.list_workflow_run_artifacts("rust-gcc", "testing", *run);
-            if let Some(page) = list.send().await?.value {
-                for artifact in page {
-                    let path = Path::new(&artifact.name);
-                    if path
-                        .extension()
-                        .map_or(false, |ext| ext.eq_ignore_ascii_case("json"))
-                    {
-                        archives.push((*run, download_artifact(&actions, artifact.id).await?));
-                    }
+            let route = format!(
+                "/repos/{}/{}/actions/runs/{}/artifacts",
+                source.owner, source.repo, run
+            );
+            let response = self.retry("list artifacts", || self.get(&route)).await?;
+            let list = Page::<WorkflowListArtifact>::from_response(response).await?;
+
+            for artifact in list {
+                if !has_json_extension(&artifact.name) {
+                    continue;
+                }
+
+                // GitHub deletes artifacts after a while, and only answers with a
+                // 410 when we try to download them
+                if artifact.expired {
+                    warn!(
+                        "{}: skipping expired artifact {} of run {}",
+                        source, artifact.id, run
+                    );
+                    continue;
+                }
+
+                // Don't even download archives which are too large. GitHub could
+                // lie about their size, so it is checked again while downloading
+                let size = artifact.size_in_bytes as u64;
+                if size > self.limits.compressed {
+                    let e = LimitExceeded::Compressed(size, self.limits.compressed);
+                    warn!(
+                        "{}: skipping artifact {} of run {}: {}",
+                        source, artifact.id, run, e
+                    );
+                    continue;
                 }
+
+                artifacts.push((*run, artifact.id));
             }
         }
 
-        Ok(archives)
+        let downloads = artifacts.into_iter().map(|(run, artifact)| async move {
+            let _permit = self
+                .downloads
+                .acquire()
+                .await
+                .expect("the download semaphore is never closed");
+
+            match self.download(source, artifact).await {
+                Ok(archive) => Ok(Some((run, archive))),
+                Err(Error::Limit(e)) => {
+                    warn!(
+                        "{}: skipping artifact {} of run {}: {}",
+                        source, artifact, run, e
+                    );
+                    Ok(None)
+                }
+                Err(e) => Err(e),
+            }
+        });
+
+        Ok(future::try_join_all(downloads)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
     }
 }
 
-pub fn extract_json(artifact: Archive) -> Result<Vec<u8>, zip::result::ZipError> {
-    let reader = BufReader::new(Cursor::new(artifact.0));
+fn has_json_extension(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// Read the testsuite results contained in every JSON file of an archive. Each
+/// file can contain either a single result or an array of results. Files which
+/// cannot be read or parsed do not prevent the others from being used: their
+/// errors are returned alongside them. The whole archive is rejected if it exceeds
+/// one of the `limits`
+pub fn extract_json(artifact: Archive, limits: &Limits) -> Result<Vec<Entry>, Error> {
+    let compressed = artifact.bytes.len() as u64;
+    if compressed > limits.compressed {
+        return Err(LimitExceeded::Compressed(compressed, limits.compressed).into());
+    }
+
+    let reader = BufReader::new(Cursor::new(artifact.bytes));
     let mut zip = zip::ZipArchive::new(reader)?;
+    if zip.len() > limits.entries {
+        return Err(LimitExceeded::Entries(zip.len(), limits.entries).into());
+    }
 
-    // We're always looking for the first file
-    let mut file = zip.by_index(0)?;
-    let mut bytes = vec![];
-    std::io::copy(&mut file, &mut bytes)?;
+    // The sizes written in the archive can't be trusted, so we count the bytes we
+    // actually decompress instead
+    let by_ratio = compressed.saturating_mul(limits.ratio);
+    let mut remaining = limits.decompressed.min(by_ratio);
+    let mut entries = vec![];
 
-    Ok(bytes)
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        if !file.is_file() || !has_json_extension(file.name()) {
+            continue;
+        }
+
+        let mut bytes = vec![];
+        let read = (&mut file)
+            .take(remaining.saturating_add(1))
+            .read_to_end(&mut bytes);
+
+        if bytes.len() as u64 > remaining {
+            return Err(if by_ratio < limits.decompressed {
+                LimitExceeded::Ratio(limits.ratio).into()
+            } else {
+                LimitExceeded::Decompressed(limits.decompressed).into()
+            });
+        }
+        remaining -= bytes.len() as u64;
+
+        let results = match read {
+            Ok(_) => TestsuiteResult::all_from_bytes(&bytes).map_err(EntryError::from),
+            Err(e) => Err(EntryError::from(e)),
+        };
+
+        entries.push(Entry {
+            name: file.name().to_string(),
+            results,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use std::sync::Mutex;
+
+    use serde_json::json;
+    use zip::write::{FileOptions, ZipWriter};
+    use zip::CompressionMethod;
+
+    use crate::stand_in::{self, Response};
+
+    const VALID0: &str = include_str!("../../mock/valid0.json");
+    const VALID1: &str = include_str!("../../mock/valid1.json");
+
+    fn archive(files: &[(&str, &str)]) -> Archive {
+        archive_with(files, CompressionMethod::Deflated)
+    }
+
+    fn archive_with(files: &[(&str, &str)], method: CompressionMethod) -> Archive {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        let options = FileOptions::default().compression_method(method);
+
+        for (name, contents) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+
+        Archive {
+            artifact: ArtifactId(1),
+            bytes: zip.finish().unwrap().into_inner(),
+        }
+    }
+
+    fn extract(archive: Archive) -> Result<Vec<Entry>, Error> {
+        extract_json(archive, &Limits::default())
+    }
+
+    fn names(entry: &Entry) -> Vec<String> {
+        entry
+            .results
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|json| json.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn reads_every_json_file() {
+        let entries = extract(archive(&[
+            ("README.md", "# Results"),
+            ("valid0.json", VALID0),
+            ("nested/valid1.json", VALID1),
+        ]))
+        .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "valid0.json");
+        assert_eq!(entries[1].name, "nested/valid1.json");
+        assert!(entries.iter().all(|entry| entry.results.is_ok()));
+    }
+
+    #[test]
+    fn reads_arrays_of_results() {
+        let array = format!("[{VALID0}, {VALID1}]");
+        let entries = extract(archive(&[("results.json", &array)])).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(names(&entries[0]).len(), 2);
+    }
+
+    #[test]
+    fn reports_invalid_files() {
+        let entries = extract(archive(&[
+            ("invalid.json", "{ \"name\": "),
+            ("valid0.json", VALID0),
+        ]))
+        .unwrap();
+
+        assert!(matches!(entries[0].results, Err(EntryError::Parse(_))));
+        assert!(entries[1].results.is_ok());
+    }
+
+    #[test]
+    fn rejects_large_archives() {
+        let archive = archive(&[("valid0.json", VALID0)]);
+        let limits = Limits {
+            compressed: archive.bytes.len() as u64 - 1,
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            extract_json(archive, &limits),
+            Err(Error::Limit(LimitExceeded::Compressed(..)))
+        ));
+    }
+
+    #[test]
+    fn rejects_archives_with_too_many_files() {
+        let names: Vec<_> = (0..5).map(|i| format!("{i}.json")).collect();
+        let files: Vec<_> = names.iter().map(|name| (name.as_str(), VALID0)).collect();
+        let limits = Limits {
+            entries: 4,
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            extract_json(archive(&files), &limits),
+            Err(Error::Limit(LimitExceeded::Entries(5, 4)))
+        ));
+    }
+
+    #[test]
+    fn rejects_archives_decompressing_to_too_much() {
+        // Stored files are not compressed, so only the decompressed size matters
+        let archive = archive_with(
+            &[("valid0.json", VALID0), ("valid1.json", VALID1)],
+            CompressionMethod::Stored,
+        );
+        let limits = Limits {
+            decompressed: VALID0.len() as u64 + 1,
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            extract_json(archive, &limits),
+            Err(Error::Limit(LimitExceeded::Decompressed(_)))
+        ));
+    }
+
+    #[test]
+    fn rejects_zip_bombs() {
+        // A few kilobytes of compressed whitespace, which decompresses to 16MiB
+        let padding = " ".repeat(16 * 1024 * 1024);
+        let bomb = format!("{}{}", padding, VALID0);
+        let archive = archive(&[("bomb.json", &bomb)]);
+        assert!((archive.bytes.len() as u64) < Limits::default().decompressed / 100);
+
+        assert!(matches!(
+            extract(archive),
+            Err(Error::Limit(LimitExceeded::Ratio(100)))
+        ));
+    }
+
+    fn workflow() -> Workflow {
+        "rust-gcc/testing/nightly_run.yml".parse().unwrap()
+    }
+
+    /// Fetcher sending its requests to a stand-in for GitHub, which answers with
+    /// `respond`. Returns the paths requested from the stand-in along with it
+    async fn stand_in<F>(respond: F) -> (Fetcher, Arc<Mutex<Vec<String>>>)
+    where
+        F: Fn(&str) -> Response + Send + 'static,
+    {
+        stand_in_with(Limits::default(), respond).await
+    }
+
+    async fn stand_in_with<F>(limits: Limits, respond: F) -> (Fetcher, Arc<Mutex<Vec<String>>>)
+    where
+        F: Fn(&str) -> Response + Send + 'static,
+    {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = requests.clone();
+        let url = stand_in::serve(move |path| {
+            log.lock().unwrap().push(path.to_string());
+            respond(path)
+        })
+        .await;
+
+        let instance = OctocrabBuilder::new()
+            .base_uri(url)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        (Fetcher::new(instance, limits, 4), requests)
+    }
+
+    /// A run of the nightly workflow, as listed by GitHub
+    fn run(id: u64, day: u32) -> serde_json::Value {
+        let url = "https://api.github.com/repos/rust-gcc/testing";
+        let date = format!("2023-05-{day:02}T03:00:00Z");
+        let author = json!({ "name": "bot", "email": "bot@example.org" });
+
+        json!({
+            "id": id,
+            "workflow_id": 1,
+            "node_id": "",
+            "name": "nightly",
+            "head_branch": "master",
+            "head_sha": "c7b7e297e",
+            "run_number": id,
+            "event": "schedule",
+            "status": "completed",
+            "created_at": date,
+            "updated_at": date,
+            "url": url,
+            "html_url": url,
+            "jobs_url": url,
+            "logs_url": url,
+            "check_suite_url": url,
+            "artifacts_url": url,
+            "cancel_url": url,
+            "rerun_url": url,
+            "workflow_url": url,
+            "head_commit": {
+                "id": "c7b7e297e",
+                "tree_id": "c7b7e297e",
+                "message": "",
+                "timestamp": date,
+                "author": author,
+                "committer": author,
+            },
+            "repository": { "id": 1, "name": "testing", "url": url },
+        })
+    }
+
+    const RUNS: &str = "/repos/rust-gcc/testing/actions/workflows/nightly_run.yml/runs";
+
+    /// Two pages of runs, newest first
+    fn runs_pages(path: &str) -> Response {
+        match path.strip_prefix(RUNS) {
+            Some("?per_page=100") => Response::json(json!({
+                "total_count": 4,
+                "workflow_runs": [run(5, 22), run(4, 21)],
+            }))
+            .header(
+                "link",
+                &format!("<{RUNS}?per_page=100&page=2>; rel=\"next\""),
+            ),
+            Some("?per_page=100&page=2") => Response::json(json!({
+                "total_count": 4,
+                "workflow_runs": [run(3, 20), run(2, 19)],
+            })),
+            _ => Response::new(404, "{}"),
+        }
+    }
+
+    fn ids(runs: &[Run]) -> Vec<u64> {
+        runs.iter().map(|run| run.id.0).collect()
+    }
+
+    fn pages_requested(requests: &Mutex<Vec<String>>) -> usize {
+        let requests = requests.lock().unwrap();
+        requests
+            .iter()
+            .filter(|path| path.starts_with(RUNS))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn walks_every_page_of_runs() {
+        let (fetcher, requests) = stand_in(runs_pages).await;
+
+        let runs = fetcher.runs(&workflow(), None).await.unwrap();
+
+        assert_eq!(ids(&runs), [5, 4, 3, 2]);
+        assert_eq!(pages_requested(&requests), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_walking_runs_once_they_are_too_old() {
+        let (fetcher, requests) = stand_in(runs_pages).await;
+
+        let since = NaiveDate::from_ymd_opt(2023, 5, 22);
+        let runs = fetcher.runs(&workflow(), since).await.unwrap();
+
+        assert_eq!(ids(&runs), [5]);
+        // The first page already contains a run older than `since`
+        assert_eq!(pages_requested(&requests), 1);
+    }
+
+    /// The artifacts of run 1, as listed by GitHub: a single one, which claims to
+    /// be 10 bytes large
+    fn artifacts(expired: bool) -> Response {
+        let url = "https://api.github.com/repos/rust-gcc/testing/actions/artifacts/7";
+        let date = "2023-05-22T03:00:00Z";
+
+        Response::json(json!({
+            "total_count": 1,
+            "artifacts": [{
+                "id": 7,
+                "node_id": "",
+                "name": "results.json",
+                "size_in_bytes": 10,
+                "url": url,
+                "archive_download_url": url,
+                "expired": expired,
+                "created_at": date,
+                "updated_at": date,
+                "expires_at": date,
+            }],
+        }))
+    }
+
+    const ARTIFACTS: &str = "/repos/rust-gcc/testing/actions/runs/1/artifacts";
+    const ARCHIVE: &str = "/repos/rust-gcc/testing/actions/artifacts/7/zip";
+
+    /// A run with a single artifact, which is `size` bytes large but claims to be
+    /// 10 bytes large
+    fn lying_artifact(size: usize) -> impl Fn(&str) -> Response {
+        move |path| match path {
+            ARTIFACTS => artifacts(false),
+            ARCHIVE => Response::new(200, vec![0; size]),
+            _ => Response::new(404, "{}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn aborts_downloads_exceeding_the_limit() {
+        let limits = Limits {
+            compressed: 1024,
+            ..Limits::default()
+        };
+        let (fetcher, _) = stand_in_with(limits, lying_artifact(1024 * 1024)).await;
+
+        assert!(matches!(
+            fetcher.download(&workflow(), ArtifactId(7)).await,
+            Err(Error::Limit(LimitExceeded::Download(1024)))
+        ));
+        // The artifact is skipped, and the others can still be used
+        let archives = fetcher
+            .result_files(&workflow(), &[RunId(1)])
+            .await
+            .unwrap();
+        assert!(archives.is_empty());
+    }
+
+    #[tokio::test]
+    async fn downloads_artifacts_within_the_limit() {
+        let limits = Limits {
+            compressed: 1024,
+            ..Limits::default()
+        };
+        let (fetcher, _) = stand_in_with(limits, lying_artifact(1024)).await;
+
+        let archives = fetcher
+            .result_files(&workflow(), &[RunId(1)])
+            .await
+            .unwrap();
+        assert_eq!(archives.len(), 1);
+        assert_eq!(archives[0].1.bytes.len(), 1024);
+    }
+
+    /// Response with a bare status, unlike the ones the stand-in sends
+    fn answer(status: u16) -> hyper::Response<()> {
+        hyper::Response::builder().status(status).body(()).unwrap()
+    }
+
+    #[test]
+    fn only_retries_transient_failures() {
+        for status in [500, 502, 503, 504, 429] {
+            assert!(is_transient(&answer(status)), "{status}");
+        }
+        for status in [200, 302, 400, 401, 403, 404, 422] {
+            assert!(!is_transient(&answer(status)), "{status}");
+        }
+
+        let mut secondary_rate_limit = answer(403);
+        secondary_rate_limit
+            .headers_mut()
+            .insert(RETRY_AFTER, "60".parse().unwrap());
+        assert!(is_transient(&secondary_rate_limit));
+    }
+
+    #[test]
+    fn doubles_the_delay_between_retries() {
+        let delays: Vec<_> = (0..RETRIES).map(backoff).collect();
+
+        assert_eq!(delays, [1, 2, 4, 8].map(Duration::from_secs),);
+    }
+
+    #[test]
+    fn reads_the_rate_limit_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(RateLimit::from_headers(&headers), None);
+
+        headers.insert("x-ratelimit-remaining", "12".parse().unwrap());
+        headers.insert("x-ratelimit-reset", "1684724400".parse().unwrap());
+        assert_eq!(
+            RateLimit::from_headers(&headers),
+            Some(RateLimit {
+                remaining: 12,
+                reset: DateTime::<Utc>::from_timestamp(1684724400, 0).unwrap(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn defers_fetching_once_the_rate_limit_is_exhausted() {
+        let reset = (Utc::now() + chrono::Duration::hours(1)).timestamp();
+        let (fetcher, requests) = stand_in(move |path| {
+            runs_pages(path)
+                .header("x-ratelimit-remaining", "0")
+                .header("x-ratelimit-reset", &reset.to_string())
+        })
+        .await;
+
+        assert!(matches!(
+            fetcher.runs(&workflow(), None).await,
+            Err(Error::RateLimited(_))
+        ));
+        // The first page told us not to request the second one
+        assert_eq!(pages_requested(&requests), 1);
+    }
+
+    #[tokio::test]
+    async fn skips_expired_artifacts() {
+        let (fetcher, requests) = stand_in(|path| match path {
+            ARTIFACTS => artifacts(true),
+            ARCHIVE => Response::new(410, r#"{ "message": "Artifact has expired" }"#),
+            _ => Response::new(404, "{}"),
+        })
+        .await;
+
+        let archives = fetcher
+            .result_files(&workflow(), &[RunId(1)])
+            .await
+            .unwrap();
+
+        assert!(archives.is_empty());
+        assert!(!requests.lock().unwrap().iter().any(|path| path == ARCHIVE));
+    }
 }
@@ -0,0 +1,194 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use serde::Deserialize;
+
+use common::{Conflict, TestsuiteResult};
+
+use super::snapshot::DayPolicy;
+
+/// How to handle a result for a run which already has a different one
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the result we already have, and list the conflict until a maintainer
+    /// cleans it up
+    #[default]
+    Manual,
+    /// Replace the result we already have with the new one
+    Replace,
+    /// Keep the result with the most passes, and then the fewest failures
+    Best,
+}
+
+/// What to do with a result conflicting with the one we already have
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Keep,
+    Replace,
+    Unresolved,
+}
+
+/// Whether two results of the same run disagree. Results which only differ by
+/// their source are the same
+pub fn differ(lhs: &TestsuiteResult, rhs: &TestsuiteResult) -> bool {
+    lhs.results != rhs.results || lhs.tests != rhs.tests
+}
+
+impl ConflictPolicy {
+    pub fn resolve(self, existing: &TestsuiteResult, incoming: &TestsuiteResult) -> Resolution {
+        match self {
+            ConflictPolicy::Manual => Resolution::Unresolved,
+            ConflictPolicy::Replace => Resolution::Replace,
+            ConflictPolicy::Best => match DayPolicy::Best.cmp(incoming, existing) {
+                Ordering::Greater => Resolution::Replace,
+                _ => Resolution::Keep,
+            },
+        }
+    }
+
+    /// Find the conflicts between loaded results, keeping a single result per run.
+    /// The order in which results were stored is unknown at this point, so only
+    /// the `Best` policy resolves these conflicts: the others leave them
+    /// unresolved, and the best result of each conflict is served in the meantime
+    pub fn detect(
+        self,
+        results: HashSet<TestsuiteResult>,
+    ) -> (HashSet<TestsuiteResult>, Vec<Conflict>) {
+        let mut kept = HashSet::new();
+        let mut conflicts = vec![];
+
+        let runs = results.into_iter().into_group_map_by(|json| {
+            (
+                json.name.clone(),
+                json.date,
+                json.commit.clone(),
+                json.run.clone(),
+            )
+        });
+        for ((name, date, commit, run), mut runs) in runs {
+            // Results are in no particular order, so sort them to always keep the
+            // same one
+            runs.sort_by(|lhs, rhs| DayPolicy::Best.cmp(rhs, lhs));
+
+            let mut results: Vec<TestsuiteResult> = vec![];
+            for json in runs {
+                if results.iter().all(|result| differ(result, &json)) {
+                    results.push(json);
+                }
+            }
+
+            if results.len() > 1 && self != ConflictPolicy::Best {
+                conflicts.push(Conflict {
+                    name,
+                    date,
+                    commit,
+                    run,
+                    results: results.clone(),
+                });
+            }
+
+            kept.extend(results.into_iter().next());
+        }
+
+        conflicts.sort_by(|lhs, rhs| lhs.date.cmp(&rhs.date).then(lhs.name.cmp(&rhs.name)));
+
+        (kept, conflicts)
+    }
+}
+
+/// Add a conflict between the result we have for a run and a new one to the
+/// list, merging it with the conflict already listed for that run if there is one
+pub fn record(
+    conflicts: &mut Vec<Conflict>,
+    existing: &TestsuiteResult,
+    incoming: TestsuiteResult,
+) {
+    match conflicts
+        .iter_mut()
+        .find(|conflict| conflict.results[0].same_run(existing))
+    {
+        Some(conflict) => {
+            if !conflict.results.iter().any(|json| !differ(json, &incoming)) {
+                conflict.results.push(incoming);
+            }
+        }
+        None => conflicts.push(Conflict {
+            name: existing.name.clone(),
+            date: existing.date,
+            commit: existing.commit.clone(),
+            run: existing.run.clone(),
+            results: vec![existing.clone(), incoming],
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures;
+
+    /// A result of the same run of ten tests, coming from `source`
+    fn result(passes: u64, source: &str) -> TestsuiteResult {
+        TestsuiteResult {
+            source: Some(source.to_string()),
+            run: Some(String::from("5047342188")),
+            ..fixtures::result(passes, 10 - passes)
+        }
+    }
+
+    #[test]
+    fn lists_conflicts_found_when_loading() {
+        let results = HashSet::from([result(8, "upload/arm"), result(6, "upload/x86")]);
+
+        let (kept, conflicts) = ConflictPolicy::Manual.detect(results);
+
+        assert_eq!(kept, HashSet::from([result(8, "upload/arm")]));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].results,
+            [result(8, "upload/arm"), result(6, "upload/x86")]
+        );
+    }
+
+    #[test]
+    fn resolves_conflicts_by_keeping_the_best_result() {
+        let results = HashSet::from([result(6, "upload/x86"), result(8, "upload/arm")]);
+
+        let (kept, conflicts) = ConflictPolicy::Best.detect(results);
+
+        assert_eq!(kept, HashSet::from([result(8, "upload/arm")]));
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            ConflictPolicy::Best.resolve(&result(8, "upload/arm"), &result(6, "upload/x86")),
+            Resolution::Keep
+        );
+    }
+
+    #[test]
+    fn ignores_results_which_only_differ_by_source() {
+        let results = HashSet::from([result(8, "upload/arm"), result(8, "upload/x86")]);
+
+        let (kept, conflicts) = ConflictPolicy::Manual.detect(results);
+
+        assert_eq!(kept.len(), 1);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merges_conflicts_of_the_same_run() {
+        let mut conflicts = vec![];
+
+        record(&mut conflicts, &result(8, "a"), result(6, "b"));
+        record(&mut conflicts, &result(8, "a"), result(4, "c"));
+        record(&mut conflicts, &result(8, "a"), result(6, "d"));
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].results,
+            [result(8, "a"), result(6, "b"), result(4, "c")]
+        );
+    }
+}
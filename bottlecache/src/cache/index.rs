@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use octocrab::models::ArtifactId;
+use serde::{Deserialize, Serialize};
+
+use super::storage::write_atomically;
+
+/// Name of the run index within the cache directory. It is a dotfile so that it
+/// is not mistaken for a testsuite result
+pub const INDEX_FILE: &str = ".runs.json";
+
+/// Results which were fetched and stored in the cache
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IndexEntry {
+    /// The GitHub artifact the results were extracted from, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<ArtifactId>,
+    pub downloaded: DateTime<Utc>,
+    /// The JSON file produced from that artifact
+    pub file: PathBuf,
+}
+
+/// Items which have already been ingested, keyed by `Item::key`, so that they are
+/// never fetched twice, even across restarts
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct RunIndex(HashMap<String, Vec<IndexEntry>>);
+
+impl RunIndex {
+    /// Load the run index stored in a cache directory, or create an empty one if
+    /// there is none yet. A corrupt index is replaced by an empty one, which gets
+    /// rebuilt as items are fetched again: results we already have are skipped
+    pub fn load(dir: &Path) -> Result<RunIndex, io::Error> {
+        let path = dir.join(INDEX_FILE);
+
+        if !path.exists() {
+            return Ok(RunIndex::default());
+        }
+
+        let contents = fs::read_to_string(&path)?;
+
+        match serde_json::from_str(&contents) {
+            Ok(index) => Ok(index),
+            Err(e) => {
+                warn!(
+                    "{}: corrupt run index, rebuilding it: {}",
+                    path.display(),
+                    e
+                );
+                Ok(RunIndex::default())
+            }
+        }
+    }
+
+    pub fn store(&self, dir: &Path) -> Result<(), io::Error> {
+        write_atomically(
+            &dir.join(INDEX_FILE),
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: String, entry: IndexEntry) {
+        self.0.entry(key).or_default().push(entry);
+    }
+
+    /// Forget about an item, so that it is fetched again
+    pub fn remove(&mut self, key: &str) {
+        self.0.remove(key);
+    }
+
+    /// Every entry of the index, along with the key of its item
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &IndexEntry)> {
+        self.0
+            .iter()
+            .flat_map(|(key, entries)| entries.iter().map(move |entry| (key, entry)))
+    }
+
+    /// Add the entries of another index to this one
+    pub fn extend(&mut self, other: RunIndex) {
+        for (key, entries) in other.0 {
+            for entry in entries {
+                self.insert(key.clone(), entry);
+            }
+        }
+    }
+}
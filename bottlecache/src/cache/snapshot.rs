@@ -0,0 +1,155 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use itertools::Itertools;
+use serde::Deserialize;
+
+use common::{Conflict, Regression, TestsuiteResult};
+
+use crate::error::Error;
+
+/// Key ordering the runs of a testsuite by id. Run ids are numbers for GitHub
+/// workflows, which must not be compared as strings
+pub fn run_order(json: &TestsuiteResult) -> (Option<u64>, Option<&str>) {
+    let run = json.run.as_deref();
+
+    (run.and_then(|run| run.parse().ok()), run)
+}
+
+/// Which run of a testsuite counts as its result for a day, when it was run
+/// several times that day
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DayPolicy {
+    /// The run with the highest id, e.g. the last run of a GitHub workflow
+    #[default]
+    Latest,
+    /// The run with the most passes, and then the fewest failures
+    Best,
+}
+
+impl DayPolicy {
+    /// Order runs so that the one counting as the result for the day is the
+    /// greatest. Runs which are equal as far as the policy is concerned are still
+    /// ordered, so that the same one is always picked
+    pub(super) fn cmp(&self, lhs: &TestsuiteResult, rhs: &TestsuiteResult) -> Ordering {
+        let latest = || {
+            run_order(lhs)
+                .cmp(&run_order(rhs))
+                .then_with(|| lhs.commit.cmp(&rhs.commit))
+                .then_with(|| lhs.source.cmp(&rhs.source))
+        };
+
+        match self {
+            DayPolicy::Latest => latest(),
+            DayPolicy::Best => lhs
+                .results
+                .passes
+                .cmp(&rhs.results.passes)
+                .then(rhs.results.failures.cmp(&lhs.results.failures))
+                .then_with(latest),
+        }
+    }
+
+    /// The run counting as the result for the day among runs of a testsuite on
+    /// that day
+    pub fn pick<'a>(
+        &self,
+        runs: impl Iterator<Item = &'a TestsuiteResult>,
+    ) -> Option<&'a TestsuiteResult> {
+        runs.max_by(|lhs, rhs| self.cmp(lhs, rhs))
+    }
+
+    /// Keep a single run per testsuite per day
+    pub fn select<'a>(
+        self,
+        runs: impl Iterator<Item = &'a TestsuiteResult>,
+    ) -> impl Iterator<Item = &'a TestsuiteResult> {
+        runs.into_group_map_by(|json| (json.name.as_str(), json.date))
+            .into_values()
+            .filter_map(move |runs| self.pick(runs.into_iter()))
+    }
+}
+
+/// Immutable view of the results held by the cache at a given point in time
+#[derive(Default, Debug)]
+pub struct Snapshot {
+    /// A single result per testsuite per day, picked among its runs according to
+    /// the configured `DayPolicy`
+    pub results: HashSet<TestsuiteResult>,
+    /// Every run of every testsuite
+    pub runs: HashSet<TestsuiteResult>,
+    /// Runs with several different results which are left for maintainers to
+    /// clean up
+    pub conflicts: Vec<Conflict>,
+    /// Regressions found in `results`, sorted by date
+    pub regressions: Vec<Regression>,
+    /// Set if the last refresh of the cache failed, in which case `results` may
+    /// be out of date
+    pub error: Option<Arc<Error>>,
+}
+
+impl Snapshot {
+    pub fn is_stale(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// Handle to the latest snapshot of the cache. Loading it never blocks, even
+/// while the cache is being refreshed, and is cheap enough to do on every request
+#[derive(Clone, Default)]
+pub struct Snapshots(Arc<ArcSwap<Snapshot>>);
+
+impl Snapshots {
+    pub fn load(&self) -> Arc<Snapshot> {
+        self.0.load_full()
+    }
+
+    /// Atomically replace the current snapshot. Readers holding on to the previous
+    /// one keep a consistent view until they drop it
+    pub(super) fn publish(&self, snapshot: Snapshot) {
+        self.0.store(Arc::new(snapshot));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::{day, result};
+
+    fn run(run: &str, passes: u64, failures: u64) -> TestsuiteResult {
+        TestsuiteResult {
+            run: Some(run.to_string()),
+            ..result(passes, failures)
+        }
+    }
+
+    #[test]
+    fn picks_latest_run() {
+        let runs = [run("998", 10, 0), run("1000", 5, 5), run("999", 8, 2)];
+
+        assert_eq!(DayPolicy::Latest.pick(runs.iter()), Some(&runs[1]));
+    }
+
+    #[test]
+    fn picks_best_run() {
+        let runs = [run("998", 10, 1), run("1000", 5, 5), run("999", 10, 0)];
+
+        assert_eq!(DayPolicy::Best.pick(runs.iter()), Some(&runs[2]));
+    }
+
+    #[test]
+    fn selects_one_run_per_day() {
+        let other_day = TestsuiteResult {
+            date: day(23),
+            ..run("1001", 1, 1)
+        };
+        let runs = [run("998", 10, 0), run("1000", 5, 5), other_day.clone()];
+
+        let selected: HashSet<_> = DayPolicy::Latest.select(runs.iter()).collect();
+        assert_eq!(selected, HashSet::from([&runs[1], &other_day]));
+    }
+}
@@ -0,0 +1,91 @@
+mod directory;
+mod github;
+mod http;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use octocrab::models::ArtifactId;
+
+pub use self::directory::Directory;
+pub use self::github::GitHub;
+pub use self::http::HttpIndex;
+use super::artifact::{Credentials, Entry, Fetcher, Limits};
+use crate::config::{Source, Workflow};
+use crate::error::Error;
+
+/// Something a source produces testsuite results in, e.g. a run of a GitHub
+/// workflow or a file
+#[derive(Clone, Debug)]
+pub struct Item {
+    /// Identifies the item within its source, so that it is never fetched twice
+    pub key: String,
+    /// Day the item was produced on, if the source knows it without fetching it
+    pub date: Option<NaiveDate>,
+}
+
+/// Results fetched from an item in one go
+#[derive(Debug)]
+pub struct Batch {
+    /// The GitHub artifact the results were extracted from, if any
+    pub artifact: Option<ArtifactId>,
+    pub entries: Vec<Entry>,
+}
+
+/// Somewhere the cache fetches testsuite results from
+#[async_trait]
+pub trait ResultSource: Send + Sync {
+    /// Name of the source, e.g. `rust-gcc/testing/nightly_run.yml`. Results fetched
+    /// from the source are tagged with it
+    fn name(&self) -> String;
+
+    /// List the items of the source, newest first when the source knows their
+    /// dates. If `since` is given, items produced before that day may be omitted
+    async fn list(&self, since: Option<NaiveDate>) -> Result<Vec<Item>, Error>;
+
+    /// Fetch the results contained in one of the source's items
+    async fn fetch(&self, item: &Item) -> Result<Vec<Batch>, Error>;
+
+    /// The GitHub workflow this source fetches results from, if any, so that GitHub
+    /// can notify us of new runs
+    fn workflow(&self) -> Option<&Workflow> {
+        None
+    }
+}
+
+/// Create the sources described in the configuration. GitHub credentials are
+/// only needed if one of them is a GitHub workflow
+pub fn from_config(
+    sources: Vec<Source>,
+    credentials: Option<Credentials>,
+    limits: Limits,
+    concurrency: usize,
+) -> Result<Vec<Arc<dyn ResultSource>>, Error> {
+    let needs_github = sources
+        .iter()
+        .any(|source| matches!(source, Source::GitHub(_)));
+    let fetcher = match credentials {
+        Some(credentials) if needs_github => {
+            Some(Fetcher::try_new(credentials, limits, concurrency)?)
+        }
+        None if needs_github => return Err(Error::MissingToken),
+        _ => None,
+    };
+
+    Ok(sources
+        .into_iter()
+        .map(|source| -> Arc<dyn ResultSource> {
+            match source {
+                Source::GitHub(workflow) => Arc::new(GitHub::new(
+                    fetcher
+                        .clone()
+                        .expect("a fetcher exists for GitHub sources"),
+                    workflow,
+                )),
+                Source::Directory { path } => Arc::new(Directory::new(path)),
+                Source::Http { index } => Arc::new(HttpIndex::new(index, limits)),
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,183 @@
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use tokio::fs;
+
+use common::TestsuiteResult;
+
+use super::{Batch, Item, ResultSource};
+use crate::cache::artifact::{Entry, EntryError};
+use crate::error::Error;
+
+/// JSON files in a local directory, e.g. where a buildbot drops its results. Each
+/// version of a file is an item, so that files added to the directory or modified
+/// are picked up on every refresh, including files which were read while they
+/// were still being written
+pub struct Directory {
+    path: PathBuf,
+}
+
+impl Directory {
+    pub fn new(path: PathBuf) -> Directory {
+        Directory { path }
+    }
+
+    /// The path of the file an item is a version of
+    fn path(item: &Item) -> Result<&str, Error> {
+        item.key
+            .rsplit_once('#')
+            .map(|(path, _)| path)
+            .ok_or_else(|| Error::InvalidItem(item.key.clone()))
+    }
+}
+
+#[async_trait]
+impl ResultSource for Directory {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    async fn list(&self, _since: Option<NaiveDate>) -> Result<Vec<Item>, Error> {
+        let mut items = vec![];
+        let mut entries = fs::read_dir(&self.path).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            if name.starts_with('.') || !name.ends_with(".json") {
+                continue;
+            }
+
+            let metadata = match fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+
+            items.push(Item {
+                key: format!(
+                    "{}#{}-{}",
+                    path.display(),
+                    modified.as_nanos(),
+                    metadata.len()
+                ),
+                date: None,
+            });
+        }
+        items.sort_by(|lhs, rhs| lhs.key.cmp(&rhs.key));
+
+        Ok(items)
+    }
+
+    async fn fetch(&self, item: &Item) -> Result<Vec<Batch>, Error> {
+        let path = Directory::path(item)?;
+        let results = match fs::read(path).await {
+            Ok(bytes) => TestsuiteResult::all_from_bytes(&bytes).map_err(EntryError::from),
+            Err(e) => Err(EntryError::from(e)),
+        };
+        // Results are identified by their file rather than by its version, so that
+        // touching a file doesn't make its results look like another run
+        let results = results.map(|results| {
+            results
+                .into_iter()
+                .map(|mut json| {
+                    json.run.get_or_insert_with(|| path.to_string());
+                    json
+                })
+                .collect()
+        });
+
+        Ok(vec![Batch {
+            artifact: None,
+            entries: vec![Entry {
+                name: path.to_string(),
+                results,
+            }],
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::fixtures;
+
+    fn paths(items: &[Item]) -> Vec<&str> {
+        items
+            .iter()
+            .map(|item| Directory::path(item).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn lists_json_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.json", "a.JSON", ".hidden.json", "notes.txt"] {
+            fs::write(dir.path().join(name), "[]").unwrap();
+        }
+        fs::create_dir(dir.path().join("nested.json")).unwrap();
+
+        let items = Directory::new(dir.path().to_path_buf())
+            .list(None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            paths(&items),
+            [
+                dir.path().join("a.JSON").display().to_string(),
+                dir.path().join("b.json").display().to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_modified_files_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.json");
+        let source = Directory::new(dir.path().to_path_buf());
+
+        // Read while it is being written
+        fs::write(&path, "[").unwrap();
+        let partial = source.list(None).await.unwrap();
+        fs::write(&path, "[]").unwrap();
+        let complete = source.list(None).await.unwrap();
+
+        assert_eq!(paths(&partial), paths(&complete));
+        assert_ne!(partial[0].key, complete[0].key);
+    }
+
+    #[tokio::test]
+    async fn fetches_results_of_each_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let valid = dir.path().join("valid.json");
+        fs::write(&valid, serde_json::to_vec(&fixtures::result(8, 2)).unwrap()).unwrap();
+        fs::write(dir.path().join("invalid.json"), "{").unwrap();
+        let source = Directory::new(dir.path().to_path_buf());
+        let items = source.list(None).await.unwrap();
+
+        let batches = source.fetch(&items[0]).await.unwrap();
+        assert!(matches!(
+            batches[0].entries[0].results,
+            Err(EntryError::Parse(_))
+        ));
+
+        // Results are identified by their file
+        let batches = source.fetch(&items[1]).await.unwrap();
+        assert_eq!(
+            batches[0].entries[0].results.as_ref().unwrap(),
+            &[TestsuiteResult {
+                run: Some(valid.display().to_string()),
+                ..fixtures::result(8, 2)
+            }]
+        );
+    }
+}
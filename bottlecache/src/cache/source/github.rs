@@ -0,0 +1,74 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use log::warn;
+use octocrab::models::RunId;
+
+use super::{Batch, Item, ResultSource};
+use crate::cache::artifact::{self, Fetcher};
+use crate::config::Workflow;
+use crate::error::Error;
+
+/// Artifacts of the runs of a GitHub Actions workflow. Each run is an item
+pub struct GitHub {
+    fetcher: Fetcher,
+    workflow: Workflow,
+}
+
+impl GitHub {
+    pub fn new(fetcher: Fetcher, workflow: Workflow) -> GitHub {
+        GitHub { fetcher, workflow }
+    }
+}
+
+#[async_trait]
+impl ResultSource for GitHub {
+    fn name(&self) -> String {
+        self.workflow.to_string()
+    }
+
+    async fn list(&self, since: Option<NaiveDate>) -> Result<Vec<Item>, Error> {
+        let runs = self.fetcher.runs(&self.workflow, since).await?;
+
+        // Runs still in progress may not have uploaded their artifacts yet, and
+        // would be indexed without any results
+        Ok(runs
+            .into_iter()
+            .filter(|run| run.status == "completed")
+            .map(|run| Item {
+                key: run.id.to_string(),
+                date: Some(run.created_at.date_naive()),
+            })
+            .collect())
+    }
+
+    async fn fetch(&self, item: &Item) -> Result<Vec<Batch>, Error> {
+        let run = RunId(
+            item.key
+                .parse()
+                .map_err(|_| Error::InvalidItem(item.key.clone()))?,
+        );
+        let mut batches = vec![];
+
+        for (run, archive) in self.fetcher.result_files(&self.workflow, &[run]).await? {
+            let id = archive.artifact;
+
+            match artifact::extract_json(archive, self.fetcher.limits()) {
+                Ok(entries) => batches.push(Batch {
+                    artifact: Some(id),
+                    entries,
+                }),
+                Err(Error::Limit(e)) => warn!(
+                    "{}: skipping artifact {} of run {}: {}",
+                    self.workflow, id, run, e
+                ),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(batches)
+    }
+
+    fn workflow(&self) -> Option<&Workflow> {
+        Some(&self.workflow)
+    }
+}
@@ -0,0 +1,221 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use reqwest::{Client, Url};
+
+use common::TestsuiteResult;
+
+use super::{Batch, Item, ResultSource};
+use crate::cache::artifact::{Entry, EntryError, LimitExceeded, Limits};
+use crate::error::Error;
+
+/// JSON files linked from an HTTP index. The index is either a JSON array of URLs,
+/// or an HTML page such as the listing of a directory served by nginx or Apache,
+/// in which case every link to a `.json` file is followed. Each file is an item
+pub struct HttpIndex {
+    client: Client,
+    index: Url,
+    limits: Limits,
+}
+
+impl HttpIndex {
+    pub fn new(index: Url, limits: Limits) -> HttpIndex {
+        HttpIndex {
+            client: Client::new(),
+            index,
+            limits,
+        }
+    }
+
+    /// Links found in an HTML page, in the order in which they appear
+    fn hrefs(html: &str) -> Vec<&str> {
+        html.split("href=")
+            .skip(1)
+            .filter_map(|link| {
+                let quote = link.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+
+                link[1..].split(quote).next()
+            })
+            .collect()
+    }
+
+    /// Download a file, giving up as soon as it is larger than the limits allow
+    async fn download(&self, url: Url) -> Result<Result<Vec<u8>, LimitExceeded>, Error> {
+        let mut response = self.client.get(url).send().await?.error_for_status()?;
+        let mut bytes = vec![];
+
+        while let Some(chunk) = response.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+
+            if bytes.len() as u64 > self.limits.decompressed {
+                return Ok(Err(LimitExceeded::Decompressed(self.limits.decompressed)));
+            }
+        }
+
+        Ok(Ok(bytes))
+    }
+}
+
+#[async_trait]
+impl ResultSource for HttpIndex {
+    fn name(&self) -> String {
+        self.index.to_string()
+    }
+
+    async fn list(&self, _since: Option<NaiveDate>) -> Result<Vec<Item>, Error> {
+        let index = self.download(self.index.clone()).await??;
+        let index = String::from_utf8_lossy(&index);
+
+        let links = match serde_json::from_str::<Vec<String>>(&index) {
+            Ok(urls) => urls,
+            Err(_) => HttpIndex::hrefs(&index)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        };
+
+        let mut items: Vec<_> = links
+            .iter()
+            .filter_map(|link| self.index.join(link).ok())
+            .filter(|url| url.path().to_lowercase().ends_with(".json"))
+            .map(|url| Item {
+                key: url.to_string(),
+                date: None,
+            })
+            .collect();
+        items.sort_by(|lhs, rhs| lhs.key.cmp(&rhs.key));
+        items.dedup_by(|lhs, rhs| lhs.key == rhs.key);
+
+        Ok(items)
+    }
+
+    async fn fetch(&self, item: &Item) -> Result<Vec<Batch>, Error> {
+        let url = Url::parse(&item.key).map_err(|_| Error::InvalidItem(item.key.clone()))?;
+        let results = match self.download(url).await? {
+            Ok(bytes) => TestsuiteResult::all_from_bytes(&bytes).map_err(EntryError::from),
+            Err(e) => Err(EntryError::from(e)),
+        };
+
+        Ok(vec![Batch {
+            artifact: None,
+            entries: vec![Entry {
+                name: item.key.clone(),
+                results,
+            }],
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures;
+    use crate::stand_in::{self, Response};
+
+    fn index(url: &str, limits: Limits) -> HttpIndex {
+        HttpIndex::new(Url::parse(&format!("{url}/results/")).unwrap(), limits)
+    }
+
+    fn keys(items: Vec<Item>) -> Vec<String> {
+        items.into_iter().map(|item| item.key).collect()
+    }
+
+    #[test]
+    fn finds_links_in_html() {
+        let html = r#"<a href="a.json">a</a> <a href='/b.json'>b</a> <a href=c.json>c</a>"#;
+
+        assert_eq!(HttpIndex::hrefs(html), ["a.json", "/b.json"]);
+    }
+
+    #[tokio::test]
+    async fn follows_relative_and_absolute_links() {
+        let url = stand_in::serve(|_| {
+            Response::new(
+                200,
+                r#"<a href="../">..</a>
+                <a href="b.json">b</a>
+                <a href='/archive/a.JSON'>a</a>
+                <a href="https://example.org/c.json">c</a>
+                <a href="b.json">b</a>
+                <a href="notes.txt">notes</a>"#,
+            )
+        })
+        .await;
+
+        let items = index(&url, Limits::default()).list(None).await.unwrap();
+
+        assert_eq!(
+            keys(items),
+            [
+                format!("{url}/archive/a.JSON"),
+                format!("{url}/results/b.json"),
+                String::from("https://example.org/c.json"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_json_indexes() {
+        let url = stand_in::serve(|_| {
+            Response::json(serde_json::json!(["b.json", "/a.json", "notes.txt"]))
+        })
+        .await;
+
+        let items = index(&url, Limits::default()).list(None).await.unwrap();
+
+        assert_eq!(
+            keys(items),
+            [format!("{url}/a.json"), format!("{url}/results/b.json")]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_indexes_exceeding_the_limit() {
+        let url = stand_in::serve(|_| Response::new(200, vec![b' '; 1024])).await;
+        let limits = Limits {
+            decompressed: 512,
+            ..Limits::default()
+        };
+
+        let error = index(&url, limits).list(None).await.unwrap_err();
+
+        assert!(matches!(
+            error,
+            Error::Limit(LimitExceeded::Decompressed(512))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fetches_results_within_the_limit() {
+        let url = stand_in::serve(|path| match path {
+            "/results/small.json" => Response::json(serde_json::json!(fixtures::result(8, 2))),
+            _ => Response::new(200, vec![b' '; 1024]),
+        })
+        .await;
+        let limits = Limits {
+            decompressed: 512,
+            ..Limits::default()
+        };
+        let source = index(&url, limits);
+
+        let small = Item {
+            key: format!("{url}/results/small.json"),
+            date: None,
+        };
+        let batches = source.fetch(&small).await.unwrap();
+        assert_eq!(
+            batches[0].entries[0].results.as_ref().unwrap(),
+            &[fixtures::result(8, 2)]
+        );
+
+        let large = Item {
+            key: format!("{url}/results/large.json"),
+            date: None,
+        };
+        let batches = source.fetch(&large).await.unwrap();
+        assert!(matches!(
+            batches[0].entries[0].results,
+            Err(EntryError::Limit(LimitExceeded::Decompressed(512)))
+        ));
+    }
+}
@@ -0,0 +1,389 @@
+mod json;
+mod sqlite;
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+use log::warn;
+use serde::Serialize;
+
+use common::TestsuiteResult;
+
+pub use self::json::JsonDir;
+pub use self::sqlite::Sqlite;
+use super::index::RunIndex;
+use crate::config::Backend;
+use crate::error::Error;
+
+/// Name of the directory malformed files are moved to, within the cache directory
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// A file, or a row of a database, which does not hold a valid testsuite result
+#[derive(Serialize, Clone, Debug)]
+pub struct Rejected {
+    /// Path to the file, or name and date of the row
+    pub entry: String,
+    pub reason: String,
+}
+
+/// Everything a storage holds
+#[derive(Default, Debug)]
+pub struct Contents {
+    pub results: HashSet<TestsuiteResult>,
+    pub index: RunIndex,
+    /// Entries which were skipped because they are malformed
+    pub rejected: Vec<Rejected>,
+}
+
+/// Where the cache persists the results it fetched, along with the index of the
+/// items they were fetched from
+pub trait Storage: Send {
+    /// Load every stored result, along with the index. Malformed entries are
+    /// skipped and reported in `Contents::rejected`
+    fn load(&mut self) -> Result<Contents, Error>;
+
+    /// Move the entries rejected by the last call to `load` out of the way, so
+    /// that they can be inspected without being loaded again
+    fn quarantine(&mut self) -> Result<(), Error>;
+
+    /// List every entry which was ever moved to quarantine
+    fn quarantined(&mut self) -> Result<Vec<Rejected>, Error>;
+
+    /// Store a result, replacing the result of the same run if there is one, i.e.
+    /// with the same testsuite, date, commit and run id. Returns the file the
+    /// result was written to, for backends which store results as files
+    fn write(&mut self, json: &TestsuiteResult) -> Result<Option<PathBuf>, Error>;
+
+    /// Store many results at once, as a single transaction for backends which
+    /// support them
+    fn write_all(&mut self, results: &HashSet<TestsuiteResult>) -> Result<(), Error> {
+        for json in results {
+            self.write(json)?;
+        }
+
+        Ok(())
+    }
+
+    /// Store the index, replacing the previous one
+    fn store_index(&mut self, index: &RunIndex) -> Result<(), Error>;
+}
+
+/// Longest name used as is in file names, which most file systems limit to 255
+/// bytes
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Whether a name, e.g. of a testsuite, is short enough and only made of letters,
+/// digits, dashes and underscores, so that it can be used as part of a file name
+pub fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Name of the lock file within the cache directory
+const LOCK_FILE: &str = ".lock";
+
+/// Exclusive lock on a cache directory, so that two instances of bottlecache never
+/// write to the same one. The lock is released when this is dropped, or when the
+/// process holding it dies
+pub struct DirLock {
+    _file: File,
+}
+
+impl DirLock {
+    pub fn acquire(dir: &Path) -> Result<DirLock, Error> {
+        let file = File::create(dir.join(LOCK_FILE))?;
+
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(DirLock { _file: file }),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                Err(Error::Locked(dir.to_path_buf()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Replace the contents of a file without ever leaving it truncated: the contents
+/// are written to a temporary file which is synced to disk and then renamed over
+/// the original one
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), io::Error> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // A dotfile, so that it is skipped when loading the directory if we crash
+    // before renaming it
+    let tmp = dir.join(format!(".{}.tmp", name));
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    // The rename itself is only durable once the directory is synced
+    File::open(dir)?.sync_all()
+}
+
+/// Open the storage of a cache directory, creating it if needed
+pub fn open(backend: Backend, dir: &Path) -> Result<Box<dyn Storage>, Error> {
+    Ok(match backend {
+        Backend::Json => Box::new(JsonDir::open(dir)?),
+        Backend::Sqlite => Box::new(Sqlite::open(dir)?),
+    })
+}
+
+/// Copy every result and index entry of a storage into another one, returning
+/// how many results were copied
+pub fn migrate(from: &mut dyn Storage, to: &mut dyn Storage) -> Result<usize, Error> {
+    let from = from.load()?;
+    let mut merged = to.load()?.index;
+
+    for rejected in &from.rejected {
+        warn!("skipping {}: {}", rejected.entry, rejected.reason);
+    }
+
+    to.write_all(&from.results)?;
+
+    merged.extend(from.index);
+    to.store_index(&merged)?;
+
+    Ok(from.results.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cache::index::INDEX_FILE;
+    use crate::fixtures;
+
+    /// Copy the mock results to a temporary directory, since opening a storage
+    /// locks its directory
+    fn mock() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+
+        for entry in fs::read_dir("mock").unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_file() {
+                fs::copy(entry.path(), dir.path().join(entry.file_name())).unwrap();
+            }
+        }
+
+        dir
+    }
+
+    #[test]
+    fn migrates_json_files_to_sqlite() {
+        let data = mock();
+        let dir = tempfile::tempdir().unwrap();
+        let mut from = JsonDir::open(data.path()).unwrap();
+        let mut to = Sqlite::open(dir.path()).unwrap();
+
+        let expected = from.load().unwrap().results;
+        assert_eq!(migrate(&mut from, &mut to).unwrap(), expected.len());
+
+        // Results survive reopening the database
+        drop(to);
+        let results = Sqlite::open(dir.path()).unwrap().load().unwrap().results;
+        assert!(!results.is_empty());
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn quarantines_malformed_files() {
+        let data = mock();
+        let mut storage = JsonDir::open(data.path()).unwrap();
+
+        let contents = storage.load().unwrap();
+        let mut rejected: Vec<_> = contents
+            .rejected
+            .iter()
+            .map(|rejected| Path::new(&rejected.entry).file_name().unwrap())
+            .collect();
+        rejected.sort();
+        assert_eq!(
+            rejected,
+            [
+                "empty.json",
+                "invalid.json",
+                "invalid_testsuite_result.json"
+            ]
+        );
+
+        storage.quarantine().unwrap();
+
+        let reloaded = storage.load().unwrap();
+        assert!(reloaded.rejected.is_empty());
+        assert_eq!(reloaded.results, contents.results);
+        assert_eq!(
+            fs::read_dir(data.path().join(QUARANTINE_DIR))
+                .unwrap()
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn lists_every_quarantined_file() {
+        let data = mock();
+        let mut storage = JsonDir::open(data.path()).unwrap();
+        storage.load().unwrap();
+        storage.quarantine().unwrap();
+
+        // A file with the same name as one quarantined earlier doesn't replace it
+        fs::write(data.path().join("empty.json"), "{").unwrap();
+        storage.load().unwrap();
+        storage.quarantine().unwrap();
+
+        let quarantined = storage.quarantined().unwrap();
+        let names: Vec<_> = quarantined
+            .iter()
+            .map(|rejected| Path::new(&rejected.entry).file_name().unwrap())
+            .collect();
+        assert_eq!(names.len(), 4);
+        assert_eq!(names[0], "empty.json");
+        assert!(names[1].to_string_lossy().starts_with("empty.json."));
+        assert_eq!(
+            names[2..],
+            ["invalid.json", "invalid_testsuite_result.json"]
+        );
+        assert!(quarantined
+            .iter()
+            .all(|rejected| rejected.reason.starts_with("invalid testsuite result")));
+    }
+
+    #[test]
+    fn names_files_after_any_run_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = JsonDir::open(dir.path()).unwrap();
+        let runs = [
+            "5047342188",
+            "results/arm.json",
+            "results_arm.json",
+            &"9".repeat(300),
+        ];
+
+        let paths: HashSet<_> = runs
+            .iter()
+            .map(|run| {
+                let json = TestsuiteResult {
+                    run: Some(run.to_string()),
+                    ..fixtures::result(8, 2)
+                };
+
+                storage.write(&json).unwrap().unwrap()
+            })
+            .collect();
+
+        assert_eq!(paths.len(), runs.len());
+        assert!(paths.contains(
+            &dir.path()
+                .join("gccrs-rustc-success-2023-05-22-c7b7e297e-5047342188.json")
+        ));
+        assert_eq!(storage.load().unwrap().results.len(), runs.len());
+    }
+
+    #[test]
+    fn keeps_files_within_the_cache_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = JsonDir::open(&dir.path().join("cache")).unwrap();
+        let json = TestsuiteResult {
+            name: String::from("../escape"),
+            commit: String::from("c7b7e297e/.."),
+            ..fixtures::result(8, 2)
+        };
+
+        let path = storage.write(&json).unwrap().unwrap();
+
+        assert_eq!(path.parent(), Some(dir.path().join("cache").as_path()));
+        assert_eq!(storage.load().unwrap().results, HashSet::from([json]));
+    }
+
+    #[test]
+    fn keeps_every_run_of_a_day() {
+        let data = mock();
+        let json = JsonDir::open(data.path())
+            .unwrap()
+            .load()
+            .unwrap()
+            .results
+            .into_iter()
+            .next()
+            .unwrap();
+        let runs = [
+            TestsuiteResult {
+                run: Some(String::from("5047342188")),
+                ..json.clone()
+            },
+            TestsuiteResult {
+                run: Some(String::from("5047342189")),
+                ..json
+            },
+        ];
+
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let storages: [Box<dyn Storage>; 2] = [
+            Box::new(JsonDir::open(dirs[0].path()).unwrap()),
+            Box::new(Sqlite::open(dirs[1].path()).unwrap()),
+        ];
+        for mut storage in storages {
+            for run in &runs {
+                storage.write(run).unwrap();
+            }
+
+            assert_eq!(storage.load().unwrap().results, HashSet::from(runs.clone()));
+        }
+    }
+
+    #[test]
+    fn rebuilds_corrupt_run_indexes() {
+        let data = mock();
+        fs::write(data.path().join(INDEX_FILE), "{ \"5047342188\": [").unwrap();
+
+        let contents = JsonDir::open(data.path()).unwrap().load().unwrap();
+
+        assert_eq!(contents.index.iter().count(), 0);
+        assert!(!contents.results.is_empty());
+    }
+
+    #[test]
+    fn locks_the_cache_directory() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let storage = JsonDir::open(dir.path()).unwrap();
+        assert!(matches!(
+            Sqlite::open(dir.path()),
+            Err(Error::Locked(path)) if path == dir.path()
+        ));
+
+        drop(storage);
+        assert!(JsonDir::open(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn writes_leave_no_temporary_files() {
+        let data = mock();
+        let dir = tempfile::tempdir().unwrap();
+        let results = JsonDir::open(data.path()).unwrap().load().unwrap().results;
+
+        let mut storage = JsonDir::open(dir.path()).unwrap();
+        for json in &results {
+            // Writing twice replaces the file
+            storage.write(json).unwrap();
+            storage.write(json).unwrap();
+        }
+
+        let files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name != LOCK_FILE)
+            .collect();
+        assert_eq!(files.len(), results.len());
+        assert!(files.iter().all(|name| name.ends_with(".json")));
+        assert_eq!(storage.load().unwrap().results, results);
+    }
+}
@@ -0,0 +1,173 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use log::info;
+use sha2::{Digest, Sha256};
+
+use common::TestsuiteResult;
+
+use super::{is_safe_name, write_atomically, Contents, DirLock, Rejected, Storage, QUARANTINE_DIR};
+use crate::cache::index::RunIndex;
+use crate::error::Error;
+
+/// A directory of JSON files, one per result. The index is stored next to them in
+/// a dotfile
+pub struct JsonDir {
+    path: PathBuf,
+    /// Files rejected by the last load
+    rejected: Vec<PathBuf>,
+    _lock: DirLock,
+}
+
+impl JsonDir {
+    pub fn open(path: &Path) -> Result<JsonDir, Error> {
+        fs::create_dir_all(path)?;
+
+        Ok(JsonDir {
+            path: path.to_path_buf(),
+            rejected: vec![],
+            _lock: DirLock::acquire(path)?,
+        })
+    }
+
+    /// `<name>-<date>-<commit>-<run>.json`, without the run for results which
+    /// don't have one. Results come from outside, and run ids may be paths or URLs
+    /// for results coming from files: parts which aren't safe to use in a file
+    /// name are replaced by their hash
+    fn file_name(json: &TestsuiteResult) -> String {
+        let mut name = format!(
+            "{}-{}-{}",
+            JsonDir::file_name_part(&json.name),
+            json.date.format("%Y-%m-%d"),
+            JsonDir::file_name_part(&json.commit)
+        );
+        if let Some(run) = &json.run {
+            name.push('-');
+            name.push_str(&JsonDir::file_name_part(run));
+        }
+
+        name + ".json"
+    }
+
+    fn file_name_part(part: &str) -> String {
+        if is_safe_name(part) {
+            return part.to_string();
+        }
+
+        Sha256::digest(part.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    fn read(path: &Path) -> Result<TestsuiteResult, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("couldn't read file: {e}"))?;
+
+        serde_json::from_str(&contents).map_err(|e| format!("invalid testsuite result: {e}"))
+    }
+}
+
+impl Storage for JsonDir {
+    fn load(&mut self) -> Result<Contents, Error> {
+        let mut contents = Contents {
+            index: RunIndex::load(&self.path)?,
+            ..Contents::default()
+        };
+        self.rejected.clear();
+
+        // The cache is as restrictive as possible, so if a file is malformed or not completely
+        // valid JSON we skip it. Dotfiles are used for bottlecache's own bookkeeping
+        // and never contain results
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') || !entry.file_type()?.is_file()
+            {
+                continue;
+            }
+
+            let path = entry.path();
+            info!("reading from {}...", path.display());
+
+            match JsonDir::read(&path) {
+                Ok(json) => {
+                    contents.results.insert(json);
+                }
+                Err(reason) => {
+                    contents.rejected.push(Rejected {
+                        entry: path.display().to_string(),
+                        reason,
+                    });
+                    self.rejected.push(path);
+                }
+            }
+        }
+
+        Ok(contents)
+    }
+
+    fn quarantine(&mut self) -> Result<(), Error> {
+        if self.rejected.is_empty() {
+            return Ok(());
+        }
+
+        let quarantine = self.path.join(QUARANTINE_DIR);
+        fs::create_dir_all(&quarantine)?;
+
+        for path in self.rejected.drain(..) {
+            if let Some(name) = path.file_name() {
+                let mut target = quarantine.join(name);
+                // Never overwrite a file quarantined earlier under the same name
+                if target.exists() {
+                    target = quarantine.join(format!(
+                        "{}.{}",
+                        name.to_string_lossy(),
+                        Utc::now().format("%Y%m%dT%H%M%S%.f")
+                    ));
+                }
+
+                fs::rename(&path, target)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn quarantined(&mut self) -> Result<Vec<Rejected>, Error> {
+        let quarantine = self.path.join(QUARANTINE_DIR);
+        if !quarantine.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut rejected = vec![];
+        for entry in fs::read_dir(quarantine)? {
+            let path = entry?.path();
+            // Why a file was rejected isn't stored, but reading it again tells us
+            let reason = match JsonDir::read(&path) {
+                Ok(_) => String::from("no longer malformed"),
+                Err(reason) => reason,
+            };
+
+            rejected.push(Rejected {
+                entry: path.display().to_string(),
+                reason,
+            });
+        }
+        rejected.sort_by(|lhs, rhs| lhs.entry.cmp(&rhs.entry));
+
+        Ok(rejected)
+    }
+
+    fn write(&mut self, json: &TestsuiteResult) -> Result<Option<PathBuf>, Error> {
+        let path = self.path.join(JsonDir::file_name(json));
+        let contents = serde_json::to_string_pretty(json).map_err(io::Error::from)?;
+        write_atomically(&path, contents.as_bytes())?;
+
+        Ok(Some(path))
+    }
+
+    fn store_index(&mut self, index: &RunIndex) -> Result<(), Error> {
+        Ok(index.store(&self.path)?)
+    }
+}
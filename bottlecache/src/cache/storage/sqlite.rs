@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection};
+
+use common::TestsuiteResult;
+
+use super::{Contents, DirLock, Rejected, Storage};
+use crate::cache::index::{IndexEntry, RunIndex};
+use crate::error::Error;
+
+/// Name of the database within the cache directory
+pub const DATABASE_FILE: &str = "bottlecache.sqlite";
+
+/// Changes to the schema, in order. The version of the schema of a database is the
+/// number of changes which were applied to it
+const MIGRATIONS: &[&str] = &[
+    "
+CREATE TABLE IF NOT EXISTS results (
+    name TEXT NOT NULL,
+    date TEXT NOT NULL,
+    commit_hash TEXT NOT NULL,
+    tests INTEGER NOT NULL,
+    passes INTEGER NOT NULL,
+    failures INTEGER NOT NULL,
+    source TEXT,
+    -- The whole result, including the status of each test if there is one
+    json TEXT NOT NULL,
+    PRIMARY KEY (name, date)
+);
+
+CREATE TABLE IF NOT EXISTS items (
+    key TEXT NOT NULL,
+    entry TEXT NOT NULL
+);
+
+-- Rows of `results` which could not be loaded
+CREATE TABLE IF NOT EXISTS quarantine (
+    name TEXT NOT NULL,
+    date TEXT NOT NULL,
+    json TEXT NOT NULL,
+    reason TEXT NOT NULL
+);
+",
+    // Results are keyed by run, since a testsuite may be run several times a day.
+    // Results without a run id have an empty one, as NULLs are never equal
+    "
+CREATE TABLE runs (
+    name TEXT NOT NULL,
+    date TEXT NOT NULL,
+    commit_hash TEXT NOT NULL,
+    run TEXT NOT NULL,
+    tests INTEGER NOT NULL,
+    passes INTEGER NOT NULL,
+    failures INTEGER NOT NULL,
+    source TEXT,
+    json TEXT NOT NULL,
+    PRIMARY KEY (name, date, commit_hash, run)
+);
+
+INSERT INTO runs (name, date, commit_hash, run, tests, passes, failures, source, json)
+    SELECT name, date, commit_hash, '', tests, passes, failures, source, json FROM results;
+
+DROP TABLE results;
+ALTER TABLE runs RENAME TO results;
+",
+];
+
+/// An SQLite database holding one row per result. Each row also holds the whole
+/// result as JSON, which is what is loaded back
+pub struct Sqlite {
+    connection: Connection,
+    /// Rows rejected by the last load, along with the reason why
+    rejected: Vec<(i64, String)>,
+    _lock: DirLock,
+}
+
+impl Sqlite {
+    /// Bring the schema of a database up to date
+    fn migrate(connection: &mut Connection) -> Result<(), Error> {
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", i + 1)?;
+            transaction.commit()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn open(dir: &Path) -> Result<Sqlite, Error> {
+        std::fs::create_dir_all(dir)?;
+        let lock = DirLock::acquire(dir)?;
+
+        let mut connection = Connection::open(dir.join(DATABASE_FILE))?;
+        Sqlite::migrate(&mut connection)?;
+
+        Ok(Sqlite {
+            connection,
+            rejected: vec![],
+            _lock: lock,
+        })
+    }
+}
+
+impl Storage for Sqlite {
+    fn load(&mut self) -> Result<Contents, Error> {
+        let mut contents = Contents::default();
+        self.rejected.clear();
+
+        let mut results = self
+            .connection
+            .prepare("SELECT rowid, name, date, json FROM results")?;
+        let rows = results.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        for row in rows {
+            let (id, name, date, json) = row?;
+
+            match serde_json::from_str(&json) {
+                Ok(json) => {
+                    contents.results.insert(json);
+                }
+                Err(e) => {
+                    let reason = format!("invalid testsuite result: {e}");
+                    contents.rejected.push(Rejected {
+                        entry: format!("{DATABASE_FILE}: {name} ({date})"),
+                        reason: reason.clone(),
+                    });
+                    self.rejected.push((id, reason));
+                }
+            }
+        }
+
+        let mut items = self.connection.prepare("SELECT key, entry FROM items")?;
+        let index = &mut contents.index;
+        for item in items.query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))? {
+            let (key, entry) = item?;
+            if let Ok(entry) = serde_json::from_str::<IndexEntry>(&entry) {
+                index.insert(key, entry);
+            }
+        }
+
+        Ok(contents)
+    }
+
+    fn quarantine(&mut self) -> Result<(), Error> {
+        let transaction = self.connection.transaction()?;
+
+        for (id, reason) in self.rejected.drain(..) {
+            transaction.execute(
+                "INSERT INTO quarantine (name, date, json, reason)
+                    SELECT name, date, json, ?2 FROM results WHERE rowid = ?1",
+                params![id, reason],
+            )?;
+            transaction.execute("DELETE FROM results WHERE rowid = ?1", params![id])?;
+        }
+
+        Ok(transaction.commit()?)
+    }
+
+    fn quarantined(&mut self) -> Result<Vec<Rejected>, Error> {
+        let mut rows = self
+            .connection
+            .prepare("SELECT name, date, reason FROM quarantine ORDER BY rowid")?;
+        let rejected = rows.query_map([], |row| {
+            Ok(Rejected {
+                entry: format!(
+                    "{DATABASE_FILE}: {} ({})",
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?
+                ),
+                reason: row.get(2)?,
+            })
+        })?;
+
+        Ok(rejected.collect::<Result<_, _>>()?)
+    }
+
+    fn write(&mut self, json: &TestsuiteResult) -> Result<Option<PathBuf>, Error> {
+        insert(&self.connection, json)?;
+
+        Ok(None)
+    }
+
+    fn write_all(&mut self, results: &HashSet<TestsuiteResult>) -> Result<(), Error> {
+        let transaction = self.connection.transaction()?;
+
+        for json in results {
+            insert(&transaction, json)?;
+        }
+
+        Ok(transaction.commit()?)
+    }
+
+    fn store_index(&mut self, index: &RunIndex) -> Result<(), Error> {
+        let transaction = self.connection.transaction()?;
+
+        transaction.execute("DELETE FROM items", [])?;
+        for (key, entry) in index.iter() {
+            transaction.execute(
+                "INSERT INTO items (key, entry) VALUES (?1, ?2)",
+                params![
+                    key,
+                    serde_json::to_string(entry).expect("entries can always be serialized")
+                ],
+            )?;
+        }
+
+        Ok(transaction.commit()?)
+    }
+}
+
+/// Insert a result, replacing the result of the same run if there is one
+fn insert(connection: &Connection, json: &TestsuiteResult) -> Result<(), Error> {
+    connection.execute(
+        "INSERT OR REPLACE INTO results
+            (name, date, commit_hash, run, tests, passes, failures, source, json)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            json.name,
+            json.date.format("%Y-%m-%d").to_string(),
+            json.commit,
+            json.run.as_deref().unwrap_or_default(),
+            json.results.tests as i64,
+            json.results.passes as i64,
+            json.results.failures as i64,
+            json.source,
+            serde_json::to_string(json).expect("results can always be serialized"),
+        ],
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::cache::{ConflictPolicy, DayPolicy, Limits};
+use crate::error::Error;
+use crate::notifier::NotifierConfig;
+use crate::regression::Thresholds;
+
+/// A GitHub Actions workflow producing testsuite results as JSON artifacts
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Workflow {
+    pub owner: String,
+    pub repo: String,
+    pub workflow: String,
+}
+
+impl Default for Workflow {
+    fn default() -> Workflow {
+        Workflow {
+            owner: String::from("rust-gcc"),
+            repo: String::from("testing"),
+            workflow: String::from("nightly_run.yml"),
+        }
+    }
+}
+
+impl fmt::Display for Workflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.owner, self.repo, self.workflow)
+    }
+}
+
+impl FromStr for Workflow {
+    type Err = String;
+
+    /// Parse a workflow from its `<owner>/<repo>/<workflow>` representation
+    fn from_str(s: &str) -> Result<Workflow, Self::Err> {
+        match s.split('/').collect::<Vec<_>>().as_slice() {
+            [owner, repo, workflow]
+                if !owner.is_empty() && !repo.is_empty() && !workflow.is_empty() =>
+            {
+                Ok(Workflow {
+                    owner: owner.to_string(),
+                    repo: repo.to_string(),
+                    workflow: workflow.to_string(),
+                })
+            }
+            _ => Err(format!(
+                "invalid workflow `{s}`: expected `<owner>/<repo>/<workflow>`"
+            )),
+        }
+    }
+}
+
+/// Somewhere testsuite results are fetched from
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum Source {
+    /// Artifacts of the runs of a GitHub Actions workflow
+    GitHub(Workflow),
+    /// JSON files in a local directory
+    Directory { path: PathBuf },
+    /// JSON files linked from an HTTP index, which is either a JSON array of URLs
+    /// or an HTML page such as a directory listing
+    Http { index: Url },
+}
+
+impl Default for Source {
+    fn default() -> Source {
+        Source::GitHub(Workflow::default())
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::GitHub(workflow) => workflow.fmt(f),
+            Source::Directory { path } => write!(f, "{}", path.display()),
+            Source::Http { index } => write!(f, "{index}"),
+        }
+    }
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    /// Parse a source from its `<owner>/<repo>/<workflow>`, `dir:<path>` or
+    /// `http(s)://<index>` representation
+    fn from_str(s: &str) -> Result<Source, Self::Err> {
+        if let Some(path) = s.strip_prefix("dir:") {
+            Ok(Source::Directory {
+                path: PathBuf::from(path),
+            })
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Url::parse(s)
+                .map(|index| Source::Http { index })
+                .map_err(|e| format!("invalid source `{s}`: {e}"))
+        } else {
+            s.parse()
+                .map(Source::GitHub)
+                .map_err(|_| format!("invalid source `{s}`: expected `<owner>/<repo>/<workflow>`, `dir:<path>` or an HTTP(S) URL"))
+        }
+    }
+}
+
+/// How the cache stores results on disk
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// One JSON file per result
+    #[default]
+    Json,
+    /// An SQLite database
+    Sqlite,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Backend, Self::Err> {
+        match s {
+            "json" => Ok(Backend::Json),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(format!(
+                "invalid storage backend `{s}`: expected `json` or `sqlite`"
+            )),
+        }
+    }
+}
+
+/// Installation of a GitHub App, used to authenticate to GitHub instead of a
+/// personal access token
+#[derive(Deserialize, Clone, Debug)]
+pub struct GitHubApp {
+    pub app_id: u64,
+    /// PEM file containing the private key of the app
+    pub private_key: PathBuf,
+    /// Installation of the app on the organization or repositories holding the
+    /// workflows we fetch results from
+    pub installation_id: u64,
+}
+
+/// Someone allowed to push results to `POST /api/testsuites`, e.g. a buildbot
+#[derive(Deserialize, Clone, Debug)]
+pub struct Uploader {
+    pub name: String,
+    /// Bearer token the uploader authenticates with
+    pub token: String,
+}
+
+/// Configuration of a bottlecache instance, read from a TOML file
+///
+/// ```toml
+/// refresh = "0 0 * * * *"
+/// concurrency = 4
+/// storage = "sqlite"
+/// day_result = "best"
+/// conflicts = "manual"
+/// webhook_secret = "..."
+/// admin_token = "..."
+///
+/// [[sources]]
+/// owner = "rust-gcc"
+/// repo = "testing"
+/// workflow = "nightly_run.yml"
+///
+/// [[sources]]
+/// path = "/srv/buildbot/results"
+///
+/// [[sources]]
+/// index = "https://example.org/results/"
+///
+/// [github]
+/// app_id = 123456
+/// private_key = "/etc/bottlecache/app.pem"
+/// installation_id = 7891011
+///
+/// [regressions]
+/// passes = 10
+/// failures = 10
+/// tests = 100
+///
+/// [limits]
+/// compressed = 67108864
+/// decompressed = 268435456
+/// entries = 1024
+/// ratio = 100
+///
+/// [notifications]
+/// retries = 3
+///
+/// [[notifications.webhooks]]
+/// url = "https://example.org/hook"
+/// format = "slack"
+///
+/// [[uploaders]]
+/// name = "buildbot-arm"
+/// token = "..."
+/// ```
+#[derive(Deserialize, Default, Debug)]
+pub struct Config {
+    #[serde(default)]
+    pub sources: Vec<Source>,
+    /// Cron expression, with seconds, at which to fetch new results
+    pub refresh: Option<String>,
+    /// How many items, such as GitHub artifacts, to download at once
+    pub concurrency: Option<usize>,
+    /// How to store results in the cache directory. Defaults to JSON files
+    pub storage: Option<Backend>,
+    /// Secret used to sign the deliveries of GitHub's `workflow_run` webhook
+    pub webhook_secret: Option<String>,
+    /// GitHub App to authenticate as, if no personal access token is given
+    pub github: Option<GitHubApp>,
+    /// Which run of a testsuite counts as its result for a day
+    #[serde(default)]
+    pub day_result: DayPolicy,
+    /// How to handle different results for the same run of a testsuite
+    #[serde(default)]
+    pub conflicts: ConflictPolicy,
+    /// Tolerated variations between consecutive results of a testsuite
+    #[serde(default)]
+    pub regressions: Thresholds,
+    /// Bounds on the artifacts downloaded from GitHub
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub notifications: NotifierConfig,
+    #[serde(default)]
+    pub uploaders: Vec<Uploader>,
+    /// Bearer token giving access to the `/api/admin` routes, which are disabled
+    /// if there is none
+    pub admin_token: Option<String>,
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        let contents = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&contents)?;
+
+        // An empty token would let anyone in with an empty bearer token
+        if let Some(uploader) = config
+            .uploaders
+            .iter()
+            .find(|uploader| uploader.token.is_empty())
+        {
+            return Err(Error::EmptyToken(format!("uploader `{}`", uploader.name)));
+        }
+        if config.admin_token.as_deref() == Some("") {
+            return Err(Error::EmptyToken(String::from("admin_token")));
+        }
+
+        Ok(config)
+    }
+}
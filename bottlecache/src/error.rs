@@ -1,12 +1,200 @@
+use std::path::PathBuf;
+use std::time::SystemTimeError;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rocket::http::Status;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::{Request, Response};
+use serde::Serialize;
 use thiserror::Error;
 
-// FIXME: Move in its own module
+use crate::cache::LimitExceeded;
+
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("couldn't fetch file creation date")]
+    FileCreationDate(#[from] SystemTimeError),
     #[error("error when using github API: {0}")]
     GitHub(#[from] octocrab::Error),
     #[error("error when extracting archive: {0}")]
     Unzipping(#[from] zip::result::ZipError),
+    #[error("archive exceeds limits: {0}")]
+    Limit(#[from] LimitExceeded),
+    #[error("error when downloading artifact: {0}")]
+    Download(#[from] hyper::Error),
+    #[error("error when fetching from HTTP source: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("`{0}` is not an item of this source")]
+    InvalidItem(String),
+    #[error("GitHub's rate limit is exhausted until {0}")]
+    RateLimited(DateTime<Utc>),
+    #[error("fetching results from GitHub requires a token or a GitHub App")]
+    MissingToken,
+    #[error("invalid GitHub App private key: {0}")]
+    PrivateKey(#[from] jsonwebtoken::errors::Error),
+    #[error("cache directory {0} is used by another instance of bottlecache")]
+    Locked(PathBuf),
+    #[error("{0} entries of the cache are malformed")]
+    Malformed(usize),
+    #[error("database error: {0}")]
+    Database(#[from] rusqlite::Error),
     #[error("writing to disk failed: {0}")]
     Disk(#[from] std::io::Error),
+    #[error("invalid configuration file: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error("invalid configuration file: {0} has an empty token")]
+    EmptyToken(String),
+    #[error("error when scheduling jobs: {0}")]
+    Scheduler(#[from] tokio_cron_scheduler::JobSchedulerError),
+    #[error("testsuite `{0}` already has this result on {1}")]
+    Duplicate(String, NaiveDate),
+    #[error("testsuite `{0}` already has a different result for this run on {1}")]
+    Conflict(String, NaiveDate),
+}
+
+/// Error returned by the API, sent to clients as a JSON body
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    #[serde(skip)]
+    status: Status,
+    /// Machine-readable identifier of the error
+    error: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: Status, error: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            error,
+            message: message.into(),
+        }
+    }
+
+    pub fn unknown_testsuite(key: &str) -> ApiError {
+        ApiError::new(
+            Status::NotFound,
+            "unknown_testsuite",
+            format!("no testsuite named `{key}`"),
+        )
+    }
+
+    pub fn no_run(key: &str, date: NaiveDate) -> ApiError {
+        ApiError::new(
+            Status::NotFound,
+            "no_run",
+            format!("testsuite `{key}` has no run on {date}"),
+        )
+    }
+
+    pub fn missing_parameter(name: &str) -> ApiError {
+        ApiError::new(
+            Status::BadRequest,
+            "missing_parameter",
+            format!("missing query parameter `{name}`"),
+        )
+    }
+
+    pub fn malformed_date(param: &str) -> ApiError {
+        ApiError::new(
+            Status::BadRequest,
+            "malformed_date",
+            format!("invalid date `{param}`: expected a date formatted as YYYY-MM-DD"),
+        )
+    }
+
+    pub fn malformed_count(name: &str, param: &str) -> ApiError {
+        ApiError::new(
+            Status::BadRequest,
+            "malformed_count",
+            format!("invalid `{name}` `{param}`: expected a non-negative integer"),
+        )
+    }
+
+    pub fn unauthorized() -> ApiError {
+        ApiError::new(
+            Status::Unauthorized,
+            "unauthorized",
+            "missing or invalid bearer token",
+        )
+    }
+
+    pub fn invalid_signature() -> ApiError {
+        ApiError::new(
+            Status::Unauthorized,
+            "invalid_signature",
+            "missing or invalid `X-Hub-Signature-256` header",
+        )
+    }
+
+    pub fn payload_too_large(message: impl Into<String>) -> ApiError {
+        ApiError::new(Status::PayloadTooLarge, "payload_too_large", message)
+    }
+
+    pub fn invalid_name(name: &str) -> ApiError {
+        ApiError::new(
+            Status::BadRequest,
+            "invalid_name",
+            format!(
+                "invalid testsuite name `{name}`: expected at most 64 letters, digits, dashes and underscores"
+            ),
+        )
+    }
+
+    pub fn malformed_result(e: serde_json::Error) -> ApiError {
+        ApiError::new(
+            Status::BadRequest,
+            "malformed_result",
+            format!("invalid testsuite result: {e}"),
+        )
+    }
+}
+
+impl From<&Error> for ApiError {
+    fn from(e: &Error) -> ApiError {
+        let (status, error) = match e {
+            // We could not reach GitHub or it gave us something we could not use
+            Error::GitHub(_) => (Status::BadGateway, "github"),
+            Error::Unzipping(_) => (Status::BadGateway, "unzipping"),
+            Error::Limit(_) => (Status::BadGateway, "limit"),
+            Error::Download(_) => (Status::BadGateway, "download"),
+            Error::Http(_) => (Status::BadGateway, "http"),
+            Error::RateLimited(_) => (Status::ServiceUnavailable, "rate_limited"),
+            Error::InvalidItem(_) => (Status::InternalServerError, "invalid_item"),
+            Error::MissingToken => (Status::InternalServerError, "missing_token"),
+            Error::PrivateKey(_) => (Status::InternalServerError, "private_key"),
+            Error::Disk(_) => (Status::InternalServerError, "disk"),
+            Error::Database(_) => (Status::InternalServerError, "database"),
+            Error::Locked(_) => (Status::InternalServerError, "locked"),
+            Error::Malformed(_) => (Status::InternalServerError, "malformed_cache"),
+            Error::FileCreationDate(_) => (Status::InternalServerError, "file_creation_date"),
+            Error::Config(_) | Error::EmptyToken(_) => (Status::InternalServerError, "config"),
+            Error::Scheduler(_) => (Status::InternalServerError, "scheduler"),
+            Error::Duplicate(..) => (Status::Conflict, "duplicate"),
+            Error::Conflict(..) => (Status::Conflict, "conflict"),
+        };
+
+        // octocrab appends a backtrace to its errors, which clients have no use for
+        let message = e.to_string();
+        let message = message.lines().next().unwrap_or_default();
+
+        ApiError::new(status, error, message)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status;
+
+        Response::build_from(Json(self).respond_to(request)?)
+            .status(status)
+            .ok()
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Error {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        ApiError::from(&self).respond_to(request)
+    }
 }
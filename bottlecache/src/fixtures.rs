@@ -0,0 +1,28 @@
+//! Results shared by the tests of several modules
+
+use chrono::NaiveDate;
+
+use common::{RunResults, TestsuiteResult};
+
+/// A day of May 2023
+pub fn day(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2023, 5, day).unwrap()
+}
+
+/// A result of the `gccrs-rustc-success` testsuite on the 22nd of May 2023, which
+/// was not produced by any source or run in particular
+pub fn result(passes: u64, failures: u64) -> TestsuiteResult {
+    TestsuiteResult {
+        name: String::from("gccrs-rustc-success"),
+        commit: String::from("c7b7e297e"),
+        date: day(22),
+        results: RunResults {
+            tests: passes + failures,
+            passes,
+            failures,
+        },
+        source: None,
+        run: None,
+        tests: None,
+    }
+}
@@ -0,0 +1,180 @@
+use hmac::{Hmac, Mac};
+use octocrab::models::RunId;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::config::Workflow;
+use crate::error::ApiError;
+
+/// What the `/hooks/github` endpoint needs to handle deliveries, managed by Rocket
+pub struct GitHubHooks {
+    /// Secret used by GitHub to sign deliveries. Deliveries are all rejected
+    /// if there is none
+    pub secret: Option<String>,
+    /// Workflows whose completed runs are fetched
+    pub workflows: Vec<Workflow>,
+}
+
+/// Headers of a webhook delivery from GitHub
+pub struct Delivery {
+    /// Type of the event, e.g. `workflow_run` or `ping`
+    pub event: String,
+    /// `X-Hub-Signature-256` header, formatted as `sha256=<hex digest>`
+    pub signature: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Delivery {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+
+        match (
+            headers.get_one("X-GitHub-Event"),
+            headers.get_one("X-Hub-Signature-256"),
+        ) {
+            (Some(event), Some(signature)) => Outcome::Success(Delivery {
+                event: event.to_string(),
+                signature: signature.to_string(),
+            }),
+            _ => Outcome::Error((Status::Unauthorized, ApiError::invalid_signature())),
+        }
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Check that `body` was signed by GitHub using `secret`. The comparison is done
+/// in constant time
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = match signature.strip_prefix("sha256=").and_then(decode_hex) {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+
+    mac.verify_slice(&signature).is_ok()
+}
+
+#[derive(Deserialize, Debug)]
+struct Owner {
+    login: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Repository {
+    name: String,
+    owner: Owner,
+}
+
+#[derive(Deserialize, Debug)]
+struct WorkflowRun {
+    id: RunId,
+    /// Path to the workflow file, e.g. `.github/workflows/nightly_run.yml`
+    path: String,
+}
+
+/// Payload of a `workflow_run` event, only keeping what we need out of it
+#[derive(Deserialize, Debug)]
+pub struct WorkflowRunEvent {
+    action: String,
+    workflow_run: WorkflowRun,
+    repository: Repository,
+}
+
+impl WorkflowRunEvent {
+    pub fn from_bytes(bytes: &[u8]) -> Result<WorkflowRunEvent, serde_json::Error> {
+        serde_json::from_slice(bytes)
+    }
+
+    /// If this event is about a run of one of `workflows` which just completed,
+    /// return that workflow along with the run
+    pub fn completed_run<'w>(&self, workflows: &'w [Workflow]) -> Option<(&'w Workflow, RunId)> {
+        if self.action != "completed" {
+            return None;
+        }
+
+        // GitHub does not care about the case of owners and repositories
+        let workflow = self.workflow_run.path.rsplit('/').next()?;
+        let workflow = workflows.iter().find(|candidate| {
+            candidate
+                .owner
+                .eq_ignore_ascii_case(&self.repository.owner.login)
+                && candidate.repo.eq_ignore_ascii_case(&self.repository.name)
+                && candidate.workflow == workflow
+        })?;
+
+        Some((workflow, self.workflow_run.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "bottlecache-test-secret";
+
+    const COMPLETED: &[u8] = include_bytes!("../mock/hooks/workflow_run_completed.json");
+    const COMPLETED_SIGNATURE: &str =
+        "sha256=705c4e0a14bba4966fae8404b880ec336fffab695718d19c83c02535a4a0119a";
+    const IN_PROGRESS: &[u8] = include_bytes!("../mock/hooks/workflow_run_in_progress.json");
+    const OTHER_WORKFLOW: &[u8] = include_bytes!("../mock/hooks/workflow_run_other_workflow.json");
+
+    #[test]
+    fn accepts_valid_signature() {
+        assert!(verify(SECRET, COMPLETED, COMPLETED_SIGNATURE));
+    }
+
+    #[test]
+    fn rejects_invalid_signatures() {
+        assert!(!verify("another-secret", COMPLETED, COMPLETED_SIGNATURE));
+        assert!(!verify(SECRET, IN_PROGRESS, COMPLETED_SIGNATURE));
+        assert!(!verify(
+            SECRET,
+            COMPLETED,
+            COMPLETED_SIGNATURE.trim_start_matches("sha256=")
+        ));
+        assert!(!verify(SECRET, COMPLETED, "sha256=not hex"));
+    }
+
+    #[test]
+    fn finds_completed_run() {
+        let event = WorkflowRunEvent::from_bytes(COMPLETED).unwrap();
+        let workflows = [Workflow::default()];
+
+        assert_eq!(
+            event.completed_run(&workflows),
+            Some((&workflows[0], RunId(5047342188)))
+        );
+    }
+
+    #[test]
+    fn ignores_runs_in_progress() {
+        let event = WorkflowRunEvent::from_bytes(IN_PROGRESS).unwrap();
+
+        assert_eq!(event.completed_run(&[Workflow::default()]), None);
+    }
+
+    #[test]
+    fn ignores_other_workflows() {
+        let event = WorkflowRunEvent::from_bytes(OTHER_WORKFLOW).unwrap();
+
+        assert_eq!(event.completed_run(&[Workflow::default()]), None);
+    }
+}
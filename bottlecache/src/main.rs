@@ -1,123 +1,707 @@
+mod auth;
 mod cache;
+mod config;
 mod error;
+#[cfg(test)]
+mod fixtures;
+mod hooks;
+mod notifier;
+mod regression;
+#[cfg(test)]
+mod stand_in;
 
 use std::collections::HashSet;
-use std::path::PathBuf;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use cache::Cache;
+use auth::{Admin, AdminToken, Authenticated, Uploaders};
+use cache::storage::{self, JsonDir, Rejected};
+use cache::{run_order, Cache, Credentials, Policies, Snapshot, Snapshots};
 use chrono::NaiveDate;
+use config::{Backend, Config, Source};
+use error::{ApiError, Error};
+use hooks::{Delivery, GitHubHooks, WorkflowRunEvent};
 use itertools::Itertools;
-use rocket::{request::FromParam, serde::json::Json, State};
+use log::{error, info};
+use notifier::{Notifier, NotifierConfig};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::response::{self, status::Created, Responder};
+use rocket::{request::FromParam, serde::json::Json, Request, State};
 use structopt::StructOpt;
 use tokio::sync::Mutex;
+use tokio_cron_scheduler::{Job, JobScheduler};
 
-use common::TestsuiteResult;
+use common::{Conflict, Diff, Page, Regression, RunDelta, Summary, TestsuiteResult};
 
 #[derive(StructOpt, Debug)]
 pub struct Args {
-    #[structopt(short, long, help = "Personal access token")]
-    token: String,
-    #[structopt(short, long, help = "Location in which to store the cached JSON files")]
+    #[structopt(
+        short,
+        long,
+        env = "BOTTLECACHE_GITHUB_TOKEN",
+        hide_env_values = true,
+        help = "Personal access token, only needed to fetch results from GitHub"
+    )]
+    token: Option<String>,
+    #[structopt(
+        long,
+        conflicts_with = "token",
+        help = "File containing the personal access token"
+    )]
+    token_file: Option<PathBuf>,
+    #[structopt(short, long, help = "Location in which to store the cached results")]
     cache: Option<PathBuf>,
     #[structopt(
         long,
-        help = "Create a mocked instance of bottlecache, which only serves runs from disk"
+        help = "How to store results in the cache: `json` files or an `sqlite` database. Defaults to json"
+    )]
+    storage: Option<Backend>,
+    #[structopt(long, help = "TOML configuration file")]
+    config: Option<PathBuf>,
+    #[structopt(
+        long = "source",
+        value_name = "source",
+        number_of_values = 1,
+        help = "Where to fetch results from: a GitHub workflow as <owner>/<repo>/<workflow>, a local directory as dir:<path> or the URL of an HTTP index. Can be given multiple times"
+    )]
+    sources: Vec<Source>,
+    #[structopt(
+        long,
+        help = "Cron expression (with seconds) at which to fetch new results. Defaults to every hour"
+    )]
+    refresh: Option<String>,
+    #[structopt(
+        long,
+        help = "How many items, such as GitHub artifacts, to download at once. Defaults to 4"
     )]
-    mock: bool,
+    concurrency: Option<usize>,
+    #[structopt(
+        long,
+        help = "Refuse to start if the cache holds malformed files, instead of quarantining them"
+    )]
+    strict: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Fetch and store every result produced in a window of time, then exit
+    Backfill {
+        #[structopt(long, help = "First day to fetch results for (YYYY-MM-DD)")]
+        since: NaiveDate,
+        #[structopt(long, help = "Last day to fetch results for (YYYY-MM-DD)")]
+        until: NaiveDate,
+    },
+    /// Import the results and index of a directory of JSON files, such as an
+    /// existing `data/` directory, into the cache, then exit
+    Migrate {
+        #[structopt(long, help = "Directory to import")]
+        from: PathBuf,
+    },
 }
 
+/// Fetch new results at the start of every hour
+const DEFAULT_REFRESH: &str = "0 0 * * * *";
+
+/// Download four artifacts at once, which is enough to make backfills much faster
+/// without tripping GitHub's secondary rate limits
+const DEFAULT_CONCURRENCY: usize = 4;
+
 struct NaiveDateRequest(NaiveDate);
 
+/// A date parameter which is not formatted as YYYY-MM-DD
 #[derive(Debug)]
-struct NaiveDateError;
+struct NaiveDateError<'r>(&'r str);
 
 impl<'r> FromParam<'r> for NaiveDateRequest {
-    type Error = chrono::ParseError;
+    type Error = NaiveDateError<'r>;
 
     fn from_param(param: &'r str) -> Result<Self, Self::Error> {
-        // FIXME: What format to use here? How to enforce it?
-        let date = NaiveDate::parse_from_str(param, "%Y-%m-%d")?;
+        let date =
+            NaiveDate::parse_from_str(param, "%Y-%m-%d").map_err(|_| NaiveDateError(param))?;
 
         Ok(NaiveDateRequest(date))
     }
 }
 
-#[rocket::get("/api/testsuites/<key>")]
-async fn testsuite_by_key(state: &State<Mutex<Cache>>, key: &str) -> Json<Vec<TestsuiteResult>> {
-    // FIXME: Can we unwrap here?
-    // FIXME: Can we improve this error handling?
-    let mut cache = state.inner().lock().await;
-    let data = cache.data().await.expect("could not fetch data");
+impl From<NaiveDateError<'_>> for ApiError {
+    fn from(e: NaiveDateError<'_>) -> ApiError {
+        ApiError::malformed_date(e.0)
+    }
+}
 
-    Json(data.into_iter().filter(|json| json.name == key).collect())
+/// Response served from a snapshot of the cache. If the last refresh failed,
+/// the response is flagged as stale using the `X-Bottlecache-Stale` header
+struct Snapshotted<R> {
+    inner: R,
+    stale: bool,
 }
 
-#[rocket::get("/api/runs/<date>")]
+impl<R> Snapshotted<R> {
+    fn new(snapshot: &Snapshot, inner: R) -> Snapshotted<R> {
+        Snapshotted {
+            inner,
+            stale: snapshot.is_stale(),
+        }
+    }
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for Snapshotted<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.inner.respond_to(request)?;
+
+        if self.stale {
+            response.set_raw_header("X-Bottlecache-Stale", "true");
+        }
+
+        Ok(response)
+    }
+}
+
+/// Load the latest snapshot of the cache. This only fails if we have no data at
+/// all to serve because refreshing the cache failed
+fn load(snapshots: &Snapshots) -> Result<Arc<Snapshot>, ApiError> {
+    let snapshot = snapshots.load();
+
+    match &snapshot.error {
+        Some(e) if snapshot.results.is_empty() => Err(ApiError::from(e.as_ref())),
+        _ => Ok(snapshot),
+    }
+}
+
+/// Optional query parameters restricting the results returned by a route to a
+/// range of dates, both inclusive, and to a page of results. Results are sorted
+/// oldest first, so `offset` skips the oldest results and `limit` keeps the
+/// oldest of the remaining ones
+#[derive(rocket::FromForm)]
+struct Window<'r> {
+    from: Option<&'r str>,
+    to: Option<&'r str>,
+    offset: Option<&'r str>,
+    limit: Option<&'r str>,
+}
+
+impl Window<'_> {
+    /// Sort results by date, oldest first, and keep the ones within the window
+    fn paginate<'a>(
+        &self,
+        results: impl Iterator<Item = &'a TestsuiteResult>,
+    ) -> Result<Page<TestsuiteResult>, ApiError> {
+        let parse = |param: Option<&str>| -> Result<Option<NaiveDate>, ApiError> {
+            match param {
+                Some(param) => Ok(Some(NaiveDateRequest::from_param(param)?.0)),
+                None => Ok(None),
+            }
+        };
+        let from = parse(self.from)?;
+        let to = parse(self.to)?;
+        // Parsed here rather than by Rocket, which would answer with an HTML page
+        let count = |name: &str, param: Option<&str>| -> Result<Option<usize>, ApiError> {
+            param
+                .map(|param| {
+                    param
+                        .parse()
+                        .map_err(|_| ApiError::malformed_count(name, param))
+                })
+                .transpose()
+        };
+        let offset = count("offset", self.offset)?;
+        let limit = count("limit", self.limit)?;
+
+        let results: Vec<_> = results
+            .filter(|json| from.is_none_or(|from| json.date >= from))
+            .filter(|json| to.is_none_or(|to| json.date <= to))
+            .sorted_by(|lhs, rhs| lhs.date.cmp(&rhs.date).then(lhs.name.cmp(&rhs.name)))
+            .collect();
+        let total = results.len();
+
+        Ok(Page {
+            total,
+            results: results
+                .into_iter()
+                .skip(offset.unwrap_or(0))
+                .take(limit.unwrap_or(usize::MAX))
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+#[rocket::get("/api/testsuites/<key>?<window..>")]
+async fn testsuite_by_key(
+    snapshots: &State<Snapshots>,
+    key: &str,
+    window: Window<'_>,
+) -> Result<Snapshotted<Json<Page<TestsuiteResult>>>, ApiError> {
+    let snapshot = load(snapshots)?;
+
+    if !snapshot.results.iter().any(|json| json.name == key) {
+        return Err(ApiError::unknown_testsuite(key));
+    }
+
+    let page = window.paginate(snapshot.results.iter().filter(|json| json.name == key))?;
+
+    Ok(Snapshotted::new(&snapshot, Json(page)))
+}
+
+#[rocket::get("/api/runs/<date>?<window..>")]
 async fn runs_by_date(
-    state: &State<Mutex<Cache>>,
-    date: NaiveDateRequest,
-) -> Json<Vec<TestsuiteResult>> {
-    // FIXME: Can we unwrap here?
-    // FIXME: Can we improve this error handling?
-    let mut cache = state.inner().lock().await;
-    let runs = cache.data().await.expect("could not fetch data");
-
-    // There is only one run per testsuite per day
-    Json(
-        runs.into_iter()
-            .filter(|json| json.date == date.0)
-            .collect(),
-    )
+    snapshots: &State<Snapshots>,
+    date: Result<NaiveDateRequest, NaiveDateError<'_>>,
+    window: Window<'_>,
+) -> Result<Snapshotted<Json<Page<TestsuiteResult>>>, ApiError> {
+    let date = date?;
+    let snapshot = load(snapshots)?;
+
+    // Only the run counting as the result of each testsuite for that day
+    let page = window.paginate(snapshot.results.iter().filter(|json| json.date == date.0))?;
+
+    Ok(Snapshotted::new(&snapshot, Json(page)))
 }
 
 #[rocket::get("/api/dates")]
-async fn all_run_dates(state: &State<Mutex<Cache>>) -> Json<HashSet<NaiveDate>> {
-    let mut cache = state.inner().lock().await;
-    let runs = cache.data().await.expect("could not fetch data");
+async fn all_run_dates(
+    snapshots: &State<Snapshots>,
+) -> Result<Snapshotted<Json<HashSet<NaiveDate>>>, ApiError> {
+    let snapshot = load(snapshots)?;
+
+    let dates = snapshot
+        .results
+        .iter()
+        .fold(HashSet::new(), |mut set, run| {
+            set.insert(run.date);
+            set
+        });
+
+    Ok(Snapshotted::new(&snapshot, Json(dates)))
+}
+
+/// The latest result of a testsuite, along with the one before it
+fn latest_two<'a>(
+    results: impl Iterator<Item = &'a TestsuiteResult>,
+) -> Option<(&'a TestsuiteResult, Option<&'a TestsuiteResult>)> {
+    let mut results = results.sorted_by(|lhs, rhs| rhs.date.cmp(&lhs.date));
+
+    results.next().map(|latest| (latest, results.next()))
+}
+
+#[rocket::get("/api/testsuites/<key>/latest")]
+async fn latest_by_key(
+    snapshots: &State<Snapshots>,
+    key: &str,
+) -> Result<Snapshotted<Json<TestsuiteResult>>, ApiError> {
+    let snapshot = load(snapshots)?;
 
-    Json(runs.into_iter().fold(HashSet::new(), |mut set, run| {
-        set.insert(run.date);
-        set
-    }))
+    let (latest, _) = latest_two(snapshot.results.iter().filter(|json| json.name == key))
+        .ok_or_else(|| ApiError::unknown_testsuite(key))?;
+
+    Ok(Snapshotted::new(&snapshot, Json(latest.clone())))
+}
+
+#[rocket::get("/api/summary")]
+async fn summary(
+    snapshots: &State<Snapshots>,
+) -> Result<Snapshotted<Json<Vec<Summary>>>, ApiError> {
+    let snapshot = load(snapshots)?;
+
+    let summaries = snapshot
+        .results
+        .iter()
+        .into_group_map_by(|json| json.name.as_str())
+        .into_iter()
+        .sorted_by_key(|(name, _)| *name)
+        .filter_map(|(name, results)| {
+            let (latest, previous) = latest_two(results.into_iter())?;
+
+            Some(Summary {
+                name: name.to_string(),
+                latest: latest.clone(),
+                previous: previous.cloned(),
+                delta: previous.map(|previous| RunDelta::new(&previous.results, &latest.results)),
+            })
+        })
+        .collect();
+
+    Ok(Snapshotted::new(&snapshot, Json(summaries)))
+}
+
+#[rocket::get("/api/testsuites/<key>/diff?<from>&<to>")]
+async fn diff_by_key(
+    snapshots: &State<Snapshots>,
+    key: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Snapshotted<Json<Diff>>, ApiError> {
+    let from =
+        NaiveDateRequest::from_param(from.ok_or_else(|| ApiError::missing_parameter("from"))?)?;
+    let to = NaiveDateRequest::from_param(to.ok_or_else(|| ApiError::missing_parameter("to"))?)?;
+    let snapshot = load(snapshots)?;
+
+    if !snapshot.results.iter().any(|json| json.name == key) {
+        return Err(ApiError::unknown_testsuite(key));
+    }
+
+    let find = |date: NaiveDate| {
+        snapshot
+            .results
+            .iter()
+            .find(|json| json.name == key && json.date == date)
+            .cloned()
+            .ok_or_else(|| ApiError::no_run(key, date))
+    };
+
+    let diff = Diff::new(find(from.0)?, find(to.0)?);
+
+    Ok(Snapshotted::new(&snapshot, Json(diff)))
 }
 
-#[rocket::get("/api/testsuites/<key>/<date>")]
+#[rocket::get("/api/regressions")]
+async fn regressions(
+    snapshots: &State<Snapshots>,
+) -> Result<Snapshotted<Json<Vec<Regression>>>, ApiError> {
+    let snapshot = load(snapshots)?;
+
+    Ok(Snapshotted::new(
+        &snapshot,
+        Json(snapshot.regressions.clone()),
+    ))
+}
+
+// `latest` and `diff` are valid `<date>` segments as far as routing is concerned,
+// so this route needs to be tried after `latest_by_key` and `diff_by_key`
+#[rocket::get("/api/testsuites/<key>/<date>", rank = 2)]
 async fn testsuite_by_key_date(
-    state: &State<Mutex<Cache>>,
+    snapshots: &State<Snapshots>,
     key: &str,
-    date: NaiveDateRequest,
-) -> Json<Option<TestsuiteResult>> {
-    // FIXME: Can we unwrap here?
-    // FIXME: Can we improve this error handling?
-    let mut cache = state.inner().lock().await;
-    let runs = cache.data().await.expect("could not fetch data");
-
-    Json(
-        runs.into_iter()
-            .find(|json| json.name == key && json.date == date.0),
-    )
+    date: Result<NaiveDateRequest, NaiveDateError<'_>>,
+) -> Result<Snapshotted<Json<TestsuiteResult>>, ApiError> {
+    let date = date?;
+    let snapshot = load(snapshots)?;
+
+    if !snapshot.results.iter().any(|json| json.name == key) {
+        return Err(ApiError::unknown_testsuite(key));
+    }
+
+    let run = snapshot
+        .results
+        .iter()
+        .find(|json| json.name == key && json.date == date.0)
+        .ok_or_else(|| ApiError::no_run(key, date.0))?;
+
+    Ok(Snapshotted::new(&snapshot, Json(run.clone())))
+}
+
+/// Every run of a testsuite on a given day, sorted by run id
+#[rocket::get("/api/testsuites/<key>/<date>/runs")]
+async fn runs_by_key_date(
+    snapshots: &State<Snapshots>,
+    key: &str,
+    date: Result<NaiveDateRequest, NaiveDateError<'_>>,
+) -> Result<Snapshotted<Json<Vec<TestsuiteResult>>>, ApiError> {
+    let date = date?;
+    let snapshot = load(snapshots)?;
+
+    if !snapshot.results.iter().any(|json| json.name == key) {
+        return Err(ApiError::unknown_testsuite(key));
+    }
+
+    let runs: Vec<_> = snapshot
+        .runs
+        .iter()
+        .filter(|json| json.name == key && json.date == date.0)
+        .sorted_by(|lhs, rhs| run_order(lhs).cmp(&run_order(rhs)))
+        .cloned()
+        .collect();
+    if runs.is_empty() {
+        return Err(ApiError::no_run(key, date.0));
+    }
+
+    Ok(Snapshotted::new(&snapshot, Json(runs)))
 }
 
 #[rocket::get("/api/testsuites")]
-async fn testsuites(state: &State<Mutex<Cache>>) -> Json<Vec<String>> {
-    // FIXME: Can we unwrap here?
-    // FIXME: Can we improve this error handling?
-    let mut cache = state.inner().lock().await;
-    let data = cache.data().await.expect("could not fetch data");
+async fn testsuites(
+    snapshots: &State<Snapshots>,
+) -> Result<Snapshotted<Json<Vec<String>>>, ApiError> {
+    let snapshot = load(snapshots)?;
+
+    let names = snapshot
+        .results
+        .iter()
+        .map(|run| run.name.clone())
+        .unique()
+        .collect();
+
+    Ok(Snapshotted::new(&snapshot, Json(names)))
+}
+
+/// Largest result which can be pushed, in MiB. Results listing the status of
+/// every test can get big
+const MAX_PUSHED_RESULT: u64 = 16;
+
+/// Push a result to the cache, e.g. from a CI job which does not run on GitHub
+#[rocket::post("/api/testsuites", data = "<body>")]
+async fn push_testsuite(
+    cache: &State<Arc<Mutex<Cache>>>,
+    uploader: Result<Authenticated, ApiError>,
+    body: Data<'_>,
+) -> Result<Created<Json<TestsuiteResult>>, ApiError> {
+    let uploader = uploader?;
+    let body = body
+        .open(MAX_PUSHED_RESULT.mebibytes())
+        .into_bytes()
+        .await
+        .map_err(|e| ApiError::from(&Error::from(e)))?;
+    if !body.is_complete() {
+        return Err(ApiError::payload_too_large(format!(
+            "pushed results are limited to {MAX_PUSHED_RESULT}MiB"
+        )));
+    }
+
+    let json = TestsuiteResult::from_bytes(&body).map_err(ApiError::malformed_result)?;
+    // Testsuite names end up in file names and in the routes of the API
+    if !storage::is_safe_name(&json.name) {
+        return Err(ApiError::invalid_name(&json.name));
+    }
+
+    let location = format!(
+        "/api/testsuites/{}/{}",
+        json.name,
+        json.date.format("%Y-%m-%d")
+    );
+
+    let json = Cache::push(cache, &uploader.0, json)
+        .await
+        .map_err(|e| ApiError::from(&e))?;
+
+    Ok(Created::new(location).body(Json(json)))
+}
+
+/// Receive `workflow_run` events from GitHub, so that results are fetched as soon
+/// as the run producing them completes instead of on the next refresh. Runs are
+/// fetched in the background, since GitHub gives up on deliveries after ten seconds
+#[rocket::post("/hooks/github", data = "<body>")]
+async fn github_hook(
+    cache: &State<Arc<Mutex<Cache>>>,
+    hooks: &State<GitHubHooks>,
+    delivery: Result<Delivery, ApiError>,
+    body: Data<'_>,
+) -> Result<Status, ApiError> {
+    let delivery = delivery?;
+    let body = body
+        .open(1.mebibytes())
+        .into_bytes()
+        .await
+        .map_err(|e| ApiError::from(&Error::from(e)))?;
+    if !body.is_complete() {
+        return Err(ApiError::payload_too_large(
+            "webhook deliveries are limited to 1MiB",
+        ));
+    }
+
+    match &hooks.secret {
+        Some(secret) if hooks::verify(secret, &body, &delivery.signature) => {}
+        _ => return Err(ApiError::invalid_signature()),
+    }
+
+    // GitHub also sends a `ping` event when the webhook is created
+    if delivery.event != "workflow_run" {
+        return Ok(Status::NoContent);
+    }
+
+    let event = WorkflowRunEvent::from_bytes(&body)
+        .map_err(|e| ApiError::new(Status::BadRequest, "malformed_event", e.to_string()))?;
+
+    match event.completed_run(&hooks.workflows) {
+        Some((workflow, run)) => {
+            let workflow = workflow.clone();
+            let cache = cache.inner().clone();
+
+            tokio::spawn(async move {
+                if let Err(e) = Cache::fetch_run(&cache, &workflow, run).await {
+                    error!("{}: couldn't fetch run {}: {}", workflow, run, e);
+                }
+            });
+
+            Ok(Status::Accepted)
+        }
+        None => Ok(Status::NoContent),
+    }
+}
+
+/// List the malformed entries which were ever moved out of the cache
+#[rocket::get("/api/admin/quarantine")]
+async fn quarantine(
+    admin: Result<Admin, ApiError>,
+    cache: &State<Arc<Mutex<Cache>>>,
+) -> Result<Json<Vec<Rejected>>, ApiError> {
+    admin?;
+
+    let quarantined = cache.lock().await.quarantined();
+
+    quarantined.map(Json).map_err(|e| ApiError::from(&e))
+}
+
+/// List the runs with several different results which the conflict policy left
+/// unresolved
+#[rocket::get("/api/admin/conflicts")]
+async fn conflicts(
+    admin: Result<Admin, ApiError>,
+    snapshots: &State<Snapshots>,
+) -> Result<Json<Vec<Conflict>>, ApiError> {
+    admin?;
+
+    Ok(Json(snapshots.load().conflicts.clone()))
+}
 
-    Json(data.into_iter().map(|run| run.name).unique().collect())
+/// Periodically fetch new results in the background, so that requests never
+/// have to wait on GitHub
+async fn schedule_refresh(cache: Arc<Mutex<Cache>>, schedule: &str) -> Result<JobScheduler, Error> {
+    let scheduler = JobScheduler::new().await?;
+
+    let job = Job::new_async(schedule, move |_, _| {
+        let cache = cache.clone();
+
+        Box::pin(async move {
+            if let Err(e) = Cache::update(&cache).await {
+                error!("couldn't refresh cache: {}", e);
+            }
+        })
+    })?;
+
+    scheduler.add(job).await?;
+    scheduler.start().await?;
+
+    Ok(scheduler)
 }
 
-#[rocket::launch]
-async fn rocket() -> _ {
+#[rocket::main]
+async fn main() {
     let args = Args::from_args();
     env_logger::init();
 
-    let mut cache =
-        Cache::try_new(args.token, args.cache, args.mock).expect("couldn't create cache");
-    cache.data().await.expect("couldn't fetch initial cache");
+    let config = match &args.config {
+        Some(path) => Config::from_file(path).expect("couldn't read configuration file"),
+        None => Config::default(),
+    };
+
+    let backend = args.storage.or(config.storage).unwrap_or_default();
+    let storage = || -> Option<Box<dyn storage::Storage>> {
+        args.cache
+            .as_deref()
+            .map(|dir| storage::open(backend, dir).expect("couldn't open cache storage"))
+    };
+
+    if let Some(Command::Migrate { from }) = &args.command {
+        // Opening a directory creates it, which would hide a typo in its path
+        if !from.is_dir() {
+            error!("{}: no such directory to import", from.display());
+            std::process::exit(1);
+        }
+
+        let mut to = storage().expect("migrating requires a cache location");
+        let mut from = JsonDir::open(from).expect("couldn't open directory to import");
+
+        let migrated = storage::migrate(&mut from, to.as_mut()).expect("couldn't migrate results");
+        info!("imported {} results into the cache", migrated);
 
-    let cache = Mutex::new(cache);
+        return;
+    }
+
+    let mut sources = config.sources;
+    sources.extend(args.sources);
+    if sources.is_empty() {
+        sources.push(Source::default());
+    }
+
+    let workflows = sources
+        .iter()
+        .filter_map(|source| match source {
+            Source::GitHub(workflow) => Some(workflow.clone()),
+            _ => None,
+        })
+        .collect();
+    // A personal access token takes precedence over a GitHub App
+    let token =
+        resolve_token(args.token, args.token_file.as_deref()).expect("couldn't read token file");
+    let credentials = token
+        .map(Credentials::Token)
+        .or(config.github.map(Credentials::App));
+    let concurrency = args
+        .concurrency
+        .or(config.concurrency)
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .max(1);
+    let policies = Policies {
+        day_result: config.day_result,
+        conflicts: config.conflicts,
+    };
+    let sources = cache::from_config(sources, credentials, config.limits, concurrency)
+        .expect("couldn't create sources");
+
+    if let Some(Command::Backfill { since, until }) = args.command {
+        if args.cache.is_none() {
+            error!("backfilling requires a cache location, given with --cache");
+            std::process::exit(1);
+        }
+
+        // We never notify anyone about backfilled results
+        let notifier =
+            Notifier::try_new(NotifierConfig::default(), None).expect("couldn't create notifier");
+        let cache = Cache::try_new(
+            storage(),
+            sources,
+            concurrency,
+            config.regressions,
+            policies,
+            notifier,
+            args.strict,
+        )
+        .expect("couldn't create cache");
+        Cache::backfill(&Mutex::new(cache), since, until)
+            .await
+            .expect("couldn't backfill cache");
+
+        return;
+    }
+
+    let notifier = Notifier::try_new(config.notifications, args.cache.as_deref())
+        .expect("couldn't load notification log");
+    let hooks = GitHubHooks {
+        secret: config.webhook_secret,
+        workflows,
+    };
+    let cache = Cache::try_new(
+        storage(),
+        sources,
+        concurrency,
+        config.regressions,
+        policies,
+        notifier,
+        args.strict,
+    )
+    .expect("couldn't create cache");
+    let snapshots = cache.snapshots();
+    let cache = Arc::new(Mutex::new(cache));
+
+    // If we can't reach our sources, we start anyway and serve what we have. New
+    // results are then fetched when GitHub notifies us of a completed run, and
+    // polled for in case we missed a notification
+    if let Err(e) = Cache::update(&cache).await {
+        error!("couldn't fetch initial cache: {}", e);
+    }
+
+    let refresh = args
+        .refresh
+        .or(config.refresh)
+        .unwrap_or_else(|| String::from(DEFAULT_REFRESH));
+    let _scheduler = schedule_refresh(cache.clone(), &refresh)
+        .await
+        .expect("couldn't schedule cache refresh");
 
     // FIXME: Should we unwrap here?
     let cors = rocket_cors::CorsOptions::default().to_cors().unwrap();
@@ -131,8 +715,437 @@ async fn rocket() -> _ {
                 testsuite_by_key,
                 runs_by_date,
                 all_run_dates,
-                testsuite_by_key_date
+                testsuite_by_key_date,
+                runs_by_key_date,
+                latest_by_key,
+                diff_by_key,
+                summary,
+                regressions,
+                push_testsuite,
+                github_hook,
+                quarantine,
+                conflicts
             ],
         )
+        .manage(snapshots)
         .manage(cache)
+        .manage(Uploaders(config.uploaders))
+        .manage(hooks)
+        .manage(AdminToken(config.admin_token))
+        .launch()
+        .await
+        .expect("couldn't launch server");
+}
+
+/// The personal access token to use, if any. `--token` conflicts with
+/// `--token-file`, but a token coming from `BOTTLECACHE_GITHUB_TOKEN` doesn't, so
+/// the file takes precedence: it can only have been given on the command line
+fn resolve_token(token: Option<String>, token_file: Option<&Path>) -> io::Result<Option<String>> {
+    match token_file {
+        Some(path) => Ok(Some(fs::read_to_string(path)?.trim().to_string())),
+        None => Ok(token),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+
+    use cache::Limits;
+    use config::Uploader;
+    use regression::Thresholds;
+
+    const VALID0: &str = include_str!("../mock/valid0.json");
+
+    async fn client() -> Client {
+        client_with(vec![]).await
+    }
+
+    /// Client of a server fetching results from `sources`, to which results can
+    /// also be pushed
+    async fn client_with(sources: Vec<Source>) -> Client {
+        let sources = cache::from_config(sources, None, Limits::default(), 1).unwrap();
+        let cache = Cache::try_new(
+            None,
+            sources,
+            1,
+            Thresholds::default(),
+            Policies::default(),
+            Notifier::try_new(NotifierConfig::default(), None).unwrap(),
+            false,
+        )
+        .unwrap();
+        let uploaders = Uploaders(vec![Uploader {
+            name: String::from("buildbot-arm"),
+            token: String::from("hunter2"),
+        }]);
+
+        let rocket = rocket::build()
+            .mount(
+                "/",
+                rocket::routes![
+                    testsuite_by_key,
+                    runs_by_date,
+                    testsuite_by_key_date,
+                    latest_by_key,
+                    diff_by_key,
+                    summary,
+                    push_testsuite
+                ],
+            )
+            .manage(cache.snapshots())
+            .manage(Arc::new(Mutex::new(cache)))
+            .manage(uploaders);
+
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    /// Push a result, returning the status and body of the response
+    async fn push(
+        client: &Client,
+        authorization: Option<&str>,
+        body: impl Into<Vec<u8>>,
+    ) -> (Status, serde_json::Value) {
+        let mut request = client.post("/api/testsuites").body(body.into());
+        if let Some(authorization) = authorization {
+            request.add_header(Header::new("Authorization", authorization.to_string()));
+        }
+
+        let response = request.dispatch().await;
+        let status = response.status();
+
+        (status, response.into_json().await.unwrap())
+    }
+
+    /// Push a result of the `gccrs-rustc-success` testsuite on a day of May 2023
+    async fn push_day(client: &Client, day: u32, passes: u64, failures: u64) {
+        let json = TestsuiteResult {
+            date: fixtures::day(day),
+            ..fixtures::result(passes, failures)
+        };
+
+        push_result(client, &json).await;
+    }
+
+    /// Push a result which must be accepted
+    async fn push_result(client: &Client, json: &TestsuiteResult) {
+        let (status, _) = push(
+            client,
+            Some("Bearer hunter2"),
+            serde_json::to_vec(json).unwrap(),
+        )
+        .await;
+        assert_eq!(status, Status::Created);
+    }
+
+    /// Get a route, returning the status and body of the response
+    async fn get(client: &Client, uri: &str) -> (Status, serde_json::Value) {
+        let response = client.get(uri.to_string()).dispatch().await;
+        let status = response.status();
+
+        (status, response.into_json().await.unwrap())
+    }
+
+    #[rocket::async_test]
+    async fn stores_pushed_results() {
+        let client = client().await;
+
+        let (status, body) = push(&client, Some("Bearer hunter2"), VALID0).await;
+
+        assert_eq!(status, Status::Created);
+        assert_eq!(body["source"], "upload/buildbot-arm");
+    }
+
+    #[rocket::async_test]
+    async fn rejects_duplicates_and_conflicts() {
+        let client = client().await;
+        let mut conflicting: serde_json::Value = serde_json::from_str(VALID0).unwrap();
+        conflicting["results"]["passes"] = serde_json::json!(0);
+
+        push(&client, Some("Bearer hunter2"), VALID0).await;
+        let (status, body) = push(&client, Some("Bearer hunter2"), VALID0).await;
+        assert_eq!(status, Status::Conflict);
+        assert_eq!(body["error"], "duplicate");
+
+        let (status, body) = push(&client, Some("Bearer hunter2"), conflicting.to_string()).await;
+        assert_eq!(status, Status::Conflict);
+        assert_eq!(body["error"], "conflict");
+    }
+
+    #[rocket::async_test]
+    async fn rejects_invalid_tokens() {
+        let client = client().await;
+
+        for authorization in [
+            None,
+            Some("Bearer "),
+            Some("Bearer hunter"),
+            Some("hunter2"),
+        ] {
+            let (status, body) = push(&client, authorization, VALID0).await;
+
+            assert_eq!(status, Status::Unauthorized);
+            assert_eq!(body["error"], "unauthorized");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_large_results() {
+        let client = client().await;
+        let padding = " ".repeat(MAX_PUSHED_RESULT as usize * 1024 * 1024);
+
+        let (status, body) = push(&client, Some("Bearer hunter2"), padding + VALID0).await;
+
+        assert_eq!(status, Status::PayloadTooLarge);
+        assert_eq!(body["error"], "payload_too_large");
+    }
+
+    #[test]
+    fn prefers_token_files_to_the_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "from-file\n").unwrap();
+
+        let from_env = Some(String::from("from-env"));
+
+        assert_eq!(
+            resolve_token(from_env.clone(), Some(&path)).unwrap(),
+            Some(String::from("from-file"))
+        );
+        assert_eq!(resolve_token(from_env.clone(), None).unwrap(), from_env);
+        assert_eq!(resolve_token(None, None).unwrap(), None);
+        assert!(resolve_token(from_env, Some(&dir.path().join("missing"))).is_err());
+    }
+
+    #[test]
+    fn rejects_both_a_token_and_a_token_file() {
+        let args =
+            Args::from_iter_safe(["bottlecache", "--token", "secret", "--token-file", "token"]);
+
+        assert!(args.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn rejects_unsafe_testsuite_names() {
+        let client = client().await;
+        let json = VALID0.replace("\"mock\"", "\"../mock\"");
+
+        let (status, body) = push(&client, Some("Bearer hunter2"), json).await;
+
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["error"], "invalid_name");
+    }
+
+    #[rocket::async_test]
+    async fn paginates_results() {
+        let client = client().await;
+        for day in 20..24 {
+            push_day(&client, day, 10, 0).await;
+        }
+
+        let (status, body) = get(
+            &client,
+            "/api/testsuites/gccrs-rustc-success?from=2023-05-21&to=2023-05-23&offset=1&limit=1",
+        )
+        .await;
+
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["results"].as_array().unwrap().len(), 1);
+        assert_eq!(body["results"][0]["date"], "2023-05-22");
+
+        let (_, body) = get(&client, "/api/runs/2023-05-21?limit=0").await;
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["results"], serde_json::json!([]));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_malformed_counts() {
+        let client = client().await;
+        push_day(&client, 22, 10, 0).await;
+
+        for query in ["limit=abc", "offset=-1"] {
+            let uri = format!("/api/testsuites/gccrs-rustc-success?{query}");
+            let (status, body) = get(&client, &uri).await;
+
+            assert_eq!(status, Status::BadRequest);
+            assert_eq!(body["error"], "malformed_count");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn flags_stale_responses() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = client_with(vec![Source::Directory {
+            path: dir.path().join("missing"),
+        }])
+        .await;
+        push_day(&client, 22, 10, 0).await;
+
+        let uri = "/api/testsuites/gccrs-rustc-success/latest";
+        let response = client.get(uri).dispatch().await;
+        assert_eq!(response.headers().get_one("X-Bottlecache-Stale"), None);
+
+        let cache = client.rocket().state::<Arc<Mutex<Cache>>>().unwrap();
+        assert!(Cache::update(cache).await.is_err());
+
+        // The results we already had are still served
+        let response = client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("X-Bottlecache-Stale"),
+            Some("true")
+        );
+    }
+
+    #[rocket::async_test]
+    async fn reports_unknown_testsuites_and_missing_runs() {
+        let client = client().await;
+        push_day(&client, 22, 10, 0).await;
+
+        for uri in [
+            "/api/testsuites/unknown",
+            "/api/testsuites/unknown/latest",
+            "/api/testsuites/unknown/2023-05-22",
+            "/api/testsuites/unknown/diff?from=2023-05-21&to=2023-05-22",
+        ] {
+            let (status, body) = get(&client, uri).await;
+
+            assert_eq!(status, Status::NotFound, "{uri}");
+            assert_eq!(body["error"], "unknown_testsuite", "{uri}");
+        }
+
+        for uri in [
+            "/api/testsuites/gccrs-rustc-success/2023-05-21",
+            "/api/testsuites/gccrs-rustc-success/diff?from=2023-05-21&to=2023-05-22",
+        ] {
+            let (status, body) = get(&client, uri).await;
+
+            assert_eq!(status, Status::NotFound, "{uri}");
+            assert_eq!(body["error"], "no_run", "{uri}");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_malformed_dates() {
+        let client = client().await;
+        push_day(&client, 22, 10, 0).await;
+
+        for uri in [
+            "/api/runs/22-05-2023",
+            "/api/testsuites/gccrs-rustc-success/2023-5-22x",
+            "/api/testsuites/gccrs-rustc-success?from=yesterday",
+            "/api/testsuites/gccrs-rustc-success/diff?from=2023-05-21&to=today",
+        ] {
+            let (status, body) = get(&client, uri).await;
+
+            assert_eq!(status, Status::BadRequest, "{uri}");
+            assert_eq!(body["error"], "malformed_date", "{uri}");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn summarizes_the_latest_results() {
+        let client = client().await;
+        push_day(&client, 22, 8, 2).await;
+        push_day(&client, 20, 5, 5).await;
+        push_day(&client, 21, 6, 3).await;
+
+        let (status, body) = get(&client, "/api/testsuites/gccrs-rustc-success/latest").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["date"], "2023-05-22");
+
+        let (status, body) = get(&client, "/api/summary").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["name"], "gccrs-rustc-success");
+        assert_eq!(body[0]["latest"]["date"], "2023-05-22");
+        assert_eq!(body[0]["previous"]["date"], "2023-05-21");
+        assert_eq!(
+            body[0]["delta"],
+            serde_json::json!({ "tests": 1, "passes": 2, "failures": -1 })
+        );
+    }
+
+    #[rocket::async_test]
+    async fn summarizes_testsuites_with_a_single_result() {
+        let client = client().await;
+        push_day(&client, 22, 8, 2).await;
+
+        let (_, body) = get(&client, "/api/summary").await;
+
+        assert_eq!(body[0]["previous"], serde_json::Value::Null);
+        assert_eq!(body[0]["delta"], serde_json::Value::Null);
+    }
+
+    #[rocket::async_test]
+    async fn diffs_two_results() {
+        let client = client().await;
+        let tests = |statuses: &[(&str, &str)]| {
+            Some(
+                statuses
+                    .iter()
+                    .map(|(name, status)| (name.to_string(), status.to_string()))
+                    .collect(),
+            )
+        };
+        push_result(
+            &client,
+            &TestsuiteResult {
+                date: fixtures::day(21),
+                commit: String::from("0a1b2c3d4"),
+                tests: tests(&[("a.rs", "pass"), ("b.rs", "fail"), ("c.rs", "pass")]),
+                ..fixtures::result(2, 1)
+            },
+        )
+        .await;
+        push_result(
+            &client,
+            &TestsuiteResult {
+                tests: tests(&[("a.rs", "pass"), ("b.rs", "pass"), ("d.rs", "fail")]),
+                ..fixtures::result(2, 1)
+            },
+        )
+        .await;
+
+        let (status, body) = get(
+            &client,
+            "/api/testsuites/gccrs-rustc-success/diff?from=2023-05-21&to=2023-05-22",
+        )
+        .await;
+
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["from_commit"], "0a1b2c3d4");
+        assert_eq!(body["to_commit"], "c7b7e297e");
+        assert_eq!(
+            body["delta"],
+            serde_json::json!({ "tests": 0, "passes": 0, "failures": 0 })
+        );
+        assert_eq!(
+            body["changed_tests"],
+            serde_json::json!([
+                { "name": "b.rs", "from": "fail", "to": "pass" },
+                { "name": "c.rs", "from": "pass", "to": null },
+                { "name": "d.rs", "from": null, "to": "fail" },
+            ])
+        );
+    }
+
+    #[rocket::async_test]
+    async fn requires_both_ends_of_a_diff() {
+        let client = client().await;
+        push_day(&client, 22, 10, 0).await;
+
+        let (status, body) = get(
+            &client,
+            "/api/testsuites/gccrs-rustc-success/diff?from=2023-05-22",
+        )
+        .await;
+
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["error"], "missing_parameter");
+    }
 }
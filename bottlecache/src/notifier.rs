@@ -0,0 +1,327 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use common::Regression;
+
+use crate::cache::storage::write_atomically;
+
+/// Name of the log of sent notifications within the cache directory
+const LOG_FILE: &str = ".notified.json";
+
+/// Longest we wait for a webhook to answer a notification
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_TEMPLATE: &str = "Regression in `{name}` between {from} ({from_commit}) and {to} ({to_commit}): {passes} passes, {failures} failures, {tests} tests";
+
+/// Shape of the body POSTed to a webhook
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// The regression itself, as JSON
+    #[default]
+    Json,
+    /// `{ "text": <message> }`
+    Slack,
+    /// `{ "topic": <testsuite>, "content": <message> }`
+    Zulip,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub format: Format,
+    /// Message sent for Slack and Zulip webhooks. `{name}`, `{from}`, `{to}`,
+    /// `{from_commit}`, `{to_commit}`, `{tests}`, `{passes}` and `{failures}` are
+    /// replaced with the regression's details
+    pub template: Option<String>,
+}
+
+/// Configuration of the notifier, read from the `[notifications]` section of the
+/// configuration file
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NotifierConfig {
+    pub webhooks: Vec<Webhook>,
+    /// How many times to retry sending a notification
+    pub retries: u32,
+    /// Delay before the first retry, in milliseconds. It doubles on every retry
+    pub backoff: u64,
+}
+
+impl Default for NotifierConfig {
+    fn default() -> NotifierConfig {
+        NotifierConfig {
+            webhooks: vec![],
+            retries: 3,
+            backoff: 1000,
+        }
+    }
+}
+
+impl Webhook {
+    fn render(&self, regression: &Regression) -> String {
+        let template = self.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+
+        template
+            .replace("{name}", &regression.name)
+            .replace("{from}", &regression.from.to_string())
+            .replace("{to}", &regression.to.to_string())
+            .replace("{from_commit}", &regression.from_commit)
+            .replace("{to_commit}", &regression.to_commit)
+            .replace("{tests}", &format!("{:+}", regression.delta.tests))
+            .replace("{passes}", &format!("{:+}", regression.delta.passes))
+            .replace("{failures}", &format!("{:+}", regression.delta.failures))
+    }
+
+    fn body(&self, regression: &Regression) -> serde_json::Value {
+        match self.format {
+            Format::Json => json!(regression),
+            Format::Slack => json!({ "text": self.render(regression) }),
+            Format::Zulip => json!({
+                "topic": regression.name,
+                "content": self.render(regression),
+            }),
+        }
+    }
+
+    /// Identifies a notification, so that it is never sent twice
+    fn key(&self, regression: &Regression) -> String {
+        format!(
+            "{} {} {} {}",
+            self.url, regression.name, regression.from, regression.to
+        )
+    }
+}
+
+/// Is this error likely to go away if the notification is sent again? Only
+/// network errors and server errors are
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.status().is_none_or(|status| status.is_server_error())
+}
+
+/// Sends notifications about regressions to webhooks
+pub struct Notifier {
+    client: reqwest::Client,
+    config: NotifierConfig,
+    /// Notifications which were already sent. Notifications are sent one at a
+    /// time, so that the same one is never sent twice concurrently
+    sent: Mutex<HashSet<String>>,
+    /// Where to persist `sent`, so that restarts do not send notifications again
+    log: Option<PathBuf>,
+}
+
+impl Notifier {
+    /// Create a notifier, loading the log of sent notifications from the cache
+    /// directory if there is one
+    pub fn try_new(config: NotifierConfig, location: Option<&Path>) -> Result<Notifier, io::Error> {
+        let log = location.map(|dir| dir.join(LOG_FILE));
+        let sent = match &log {
+            Some(path) if path.exists() => serde_json::from_str(&fs::read_to_string(path)?)?,
+            _ => HashSet::new(),
+        };
+
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(io::Error::other)?;
+
+        Ok(Notifier {
+            client,
+            config,
+            sent: Mutex::new(sent),
+            log,
+        })
+    }
+
+    async fn post(&self, webhook: &Webhook, regression: &Regression) -> Result<(), reqwest::Error> {
+        let body = webhook.body(regression);
+        let mut delay = Duration::from_millis(self.config.backoff);
+        let mut attempt = 0;
+
+        loop {
+            let response = self
+                .client
+                .post(&webhook.url)
+                .json(&body)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            match response {
+                Ok(_) => return Ok(()),
+                // Webhooks rejecting a notification would reject it again
+                Err(e) if attempt < self.config.retries && is_transient(&e) => {
+                    warn!(
+                        "couldn't notify {}: {}. Retrying in {:?}",
+                        webhook.url, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn store(&self, sent: &HashSet<String>) -> Result<(), io::Error> {
+        match &self.log {
+            Some(path) => write_atomically(path, serde_json::to_string_pretty(sent)?.as_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    /// Notify every webhook of the given regressions, skipping the ones which were
+    /// already sent. Failing to notify a webhook is logged but not fatal
+    pub async fn notify(&self, regressions: &[Regression]) {
+        if regressions.is_empty() {
+            return;
+        }
+
+        let mut sent = self.sent.lock().await;
+
+        for regression in regressions {
+            for webhook in &self.config.webhooks {
+                let key = webhook.key(regression);
+                if sent.contains(&key) {
+                    continue;
+                }
+
+                match self.post(webhook, regression).await {
+                    Ok(()) => {
+                        info!(
+                            "notified {} of regression in {} ({})",
+                            webhook.url, regression.name, regression.to
+                        );
+                        sent.insert(key);
+                    }
+                    Err(e) => error!("couldn't notify {}: {}", webhook.url, e),
+                }
+            }
+        }
+
+        if let Err(e) = self.store(&sent) {
+            error!("couldn't store notification log: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+    use common::{RegressionKind, RunDelta};
+    use tokio::sync::mpsc;
+
+    use crate::stand_in::{self, Response};
+
+    fn regression() -> Regression {
+        Regression {
+            name: String::from("gccrs-rustc-success-no-core"),
+            from: NaiveDate::from_ymd_opt(2023, 5, 21).unwrap(),
+            to: NaiveDate::from_ymd_opt(2023, 5, 22).unwrap(),
+            from_commit: String::from("7d38d0188"),
+            to_commit: String::from("c7b7e297e"),
+            delta: RunDelta {
+                tests: 0,
+                passes: -30,
+                failures: 30,
+            },
+            kinds: vec![RegressionKind::PassesDropped, RegressionKind::FailuresRose],
+        }
+    }
+
+    /// Start an HTTP server answering with the given statuses, in order, and then
+    /// with 200. The body of every request is sent back through the channel
+    async fn stand_in(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<String>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let statuses = std::sync::Mutex::new(statuses.into_iter());
+
+        let url = stand_in::serve_requests(move |request| {
+            tx.send(String::from_utf8_lossy(&request.body).to_string())
+                .unwrap();
+
+            Response::new(statuses.lock().unwrap().next().unwrap_or(200), "")
+        })
+        .await;
+
+        (format!("{url}/hook"), rx)
+    }
+
+    fn config(url: String, format: Format) -> NotifierConfig {
+        NotifierConfig {
+            webhooks: vec![Webhook {
+                url,
+                format,
+                template: None,
+            }],
+            retries: 2,
+            backoff: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_slack_message() {
+        let (url, mut bodies) = stand_in(vec![]).await;
+        let notifier = Notifier::try_new(config(url, Format::Slack), None).unwrap();
+
+        notifier.notify(&[regression()]).await;
+
+        let body: serde_json::Value = serde_json::from_str(&bodies.recv().await.unwrap()).unwrap();
+        assert_eq!(
+            body["text"],
+            "Regression in `gccrs-rustc-success-no-core` between 2023-05-21 (7d38d0188) and 2023-05-22 (c7b7e297e): -30 passes, +30 failures, +0 tests"
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_on_server_errors() {
+        let (url, mut bodies) = stand_in(vec![500, 503]).await;
+        let notifier = Notifier::try_new(config(url, Format::Json), None).unwrap();
+
+        notifier.notify(&[regression()]).await;
+
+        for _ in 0..3 {
+            let body: Regression = serde_json::from_str(&bodies.recv().await.unwrap()).unwrap();
+            assert_eq!(body, regression());
+        }
+        assert_eq!(notifier.sent.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn never_sends_twice_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, mut bodies) = stand_in(vec![]).await;
+
+        let notifier =
+            Notifier::try_new(config(url.clone(), Format::Zulip), Some(dir.path())).unwrap();
+        notifier.notify(&[regression()]).await;
+        assert!(bodies.recv().await.is_some());
+
+        let restarted = Notifier::try_new(config(url, Format::Zulip), Some(dir.path())).unwrap();
+        restarted.notify(&[regression()]).await;
+        assert!(bodies.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_client_errors() {
+        let (url, mut bodies) = stand_in(vec![404]).await;
+        let notifier = Notifier::try_new(config(url, Format::Json), None).unwrap();
+
+        notifier.notify(&[regression()]).await;
+
+        assert!(bodies.recv().await.is_some());
+        assert!(bodies.try_recv().is_err());
+        assert!(notifier.sent.lock().await.is_empty());
+    }
+}
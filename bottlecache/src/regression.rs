@@ -0,0 +1,190 @@
+use itertools::Itertools;
+use serde::Deserialize;
+
+use common::{Regression, RegressionKind, RunDelta, TestsuiteResult};
+
+/// How much consecutive results of a testsuite can vary before the change is
+/// flagged as a regression
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(default)]
+pub struct Thresholds {
+    /// Maximum number of passes which can be lost
+    pub passes: u64,
+    /// Maximum number of new failures
+    pub failures: u64,
+    /// Maximum change, in either direction, in the number of tests
+    pub tests: u64,
+}
+
+impl Thresholds {
+    fn kinds(&self, delta: &RunDelta) -> Vec<RegressionKind> {
+        let mut kinds = vec![];
+
+        if delta.passes < -(self.passes as i64) {
+            kinds.push(RegressionKind::PassesDropped);
+        }
+        if delta.failures > self.failures as i64 {
+            kinds.push(RegressionKind::FailuresRose);
+        }
+        if delta.tests.unsigned_abs() > self.tests {
+            kinds.push(RegressionKind::TestsChanged);
+        }
+
+        kinds
+    }
+
+    /// Compare two consecutive results of a testsuite
+    pub fn compare(
+        &self,
+        previous: &TestsuiteResult,
+        current: &TestsuiteResult,
+    ) -> Option<Regression> {
+        let delta = RunDelta::new(&previous.results, &current.results);
+        let kinds = self.kinds(&delta);
+
+        if kinds.is_empty() {
+            return None;
+        }
+
+        Some(Regression {
+            name: current.name.clone(),
+            from: previous.date,
+            to: current.date,
+            from_commit: previous.commit.clone(),
+            to_commit: current.commit.clone(),
+            delta,
+            kinds,
+        })
+    }
+}
+
+/// Compare each result with the previous result of the same testsuite, and return
+/// every regression found, sorted by date
+pub fn analyze<'a>(
+    results: impl Iterator<Item = &'a TestsuiteResult>,
+    thresholds: &Thresholds,
+) -> Vec<Regression> {
+    results
+        .into_group_map_by(|json| json.name.as_str())
+        .into_values()
+        .flat_map(|results| {
+            results
+                .into_iter()
+                .sorted_by_key(|json| json.date)
+                .tuple_windows()
+                .filter_map(|(previous, current)| thresholds.compare(previous, current))
+        })
+        .sorted_by(|lhs, rhs| lhs.to.cmp(&rhs.to).then(lhs.name.cmp(&rhs.name)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::{day, result};
+
+    const THRESHOLDS: Thresholds = Thresholds {
+        passes: 2,
+        failures: 2,
+        tests: 2,
+    };
+
+    fn delta(tests: i64, passes: i64, failures: i64) -> RunDelta {
+        RunDelta {
+            tests,
+            passes,
+            failures,
+        }
+    }
+
+    fn on(date: u32, passes: u64, failures: u64) -> TestsuiteResult {
+        TestsuiteResult {
+            date: day(date),
+            commit: format!("commit-{date}"),
+            ..result(passes, failures)
+        }
+    }
+
+    #[test]
+    fn flags_each_kind_of_regression() {
+        assert_eq!(
+            THRESHOLDS.kinds(&delta(0, -3, 3)),
+            [RegressionKind::PassesDropped, RegressionKind::FailuresRose]
+        );
+        assert_eq!(
+            THRESHOLDS.kinds(&delta(3, 0, 0)),
+            [RegressionKind::TestsChanged]
+        );
+        // Losing tests is as suspicious as gaining some
+        assert_eq!(
+            THRESHOLDS.kinds(&delta(-3, 0, 0)),
+            [RegressionKind::TestsChanged]
+        );
+    }
+
+    #[test]
+    fn tolerates_changes_up_to_the_thresholds() {
+        assert!(THRESHOLDS.kinds(&delta(2, -2, 2)).is_empty());
+        assert!(THRESHOLDS.kinds(&delta(-2, -2, 2)).is_empty());
+        // Improvements are never regressions
+        assert!(THRESHOLDS.kinds(&delta(0, 100, -100)).is_empty());
+
+        assert_eq!(
+            Thresholds::default().kinds(&delta(0, -1, 0)),
+            [RegressionKind::PassesDropped]
+        );
+        assert!(Thresholds::default().kinds(&delta(0, 0, 0)).is_empty());
+    }
+
+    #[test]
+    fn compares_consecutive_results() {
+        let previous = on(21, 10, 0);
+        let current = on(22, 7, 3);
+
+        assert_eq!(
+            THRESHOLDS.compare(&previous, &current),
+            Some(Regression {
+                name: String::from("gccrs-rustc-success"),
+                from: day(21),
+                to: day(22),
+                from_commit: String::from("commit-21"),
+                to_commit: String::from("commit-22"),
+                delta: delta(0, -3, 3),
+                kinds: vec![RegressionKind::PassesDropped, RegressionKind::FailuresRose],
+            })
+        );
+        assert_eq!(THRESHOLDS.compare(&current, &previous), None);
+    }
+
+    #[test]
+    fn analyzes_each_testsuite_across_gaps_in_dates() {
+        let other = |date, passes, failures| TestsuiteResult {
+            name: String::from("gccrs-rustc-success-no-core"),
+            ..on(date, passes, failures)
+        };
+        // No results between the 15th and the 20th, nor on the 22nd
+        let results = [
+            on(23, 10, 0),
+            on(15, 10, 0),
+            on(20, 5, 5),
+            on(21, 10, 0),
+            other(21, 10, 0),
+            other(23, 1, 9),
+        ];
+
+        let regressions = analyze(results.iter(), &Thresholds::default());
+
+        let ranges: Vec<_> = regressions
+            .iter()
+            .map(|regression| (regression.name.as_str(), regression.from, regression.to))
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                ("gccrs-rustc-success", day(15), day(20)),
+                ("gccrs-rustc-success-no-core", day(21), day(23)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! HTTP server standing in for GitHub and other services in tests
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// What the stand-in answers to a request
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn json(value: serde_json::Value) -> Response {
+        Response::new(200, value.to_string()).header("content-type", "application/json")
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// A request sent to the stand-in
+pub struct Request {
+    /// Path of the request, including the query string
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+/// Start an HTTP server answering every request with the response `respond`
+/// gives for its path, including the query string. Returns the URL of the server
+pub async fn serve<F>(respond: F) -> String
+where
+    F: Fn(&str) -> Response + Send + 'static,
+{
+    serve_requests(move |request| respond(&request.path)).await
+}
+
+/// Start an HTTP server answering every request with the response `respond`
+/// gives for it. Returns the URL of the server
+pub async fn serve_requests<F>(respond: F) -> String
+where
+    F: Fn(&Request) -> Response + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 4096];
+
+            // Read until we got the headers and the whole body
+            let (head, body) = loop {
+                if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&request[..end]).to_string();
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse::<usize>().ok())?
+                        })
+                        .unwrap_or(0);
+
+                    if request.len() >= end + 4 + length {
+                        break (head, request[end + 4..end + 4 + length].to_vec());
+                    }
+                }
+
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break (String::new(), vec![]),
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            };
+
+            let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+            let response = respond(&Request { path, body });
+
+            let mut head = format!(
+                "HTTP/1.1 {} STATUS\r\ncontent-length: {}\r\nconnection: close\r\n",
+                response.status,
+                response.body.len()
+            );
+            for (name, value) in &response.headers {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+            head.push_str("\r\n");
+
+            // Clients may hang up before reading the whole response
+            let _ = stream.write_all(head.as_bytes()).await;
+            let _ = stream.write_all(&response.body).await;
+        }
+    });
+
+    url
+}
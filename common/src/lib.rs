@@ -1,3 +1,6 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+
 use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 
@@ -8,12 +11,80 @@ pub struct RunResults {
     pub failures: u64,
 }
 
-#[derive(Deserialize, Serialize, Clone, Hash, PartialEq, Eq, Debug)]
+impl RunResults {
+    /// Ratio of passing tests, between 0 and 1
+    pub fn pass_rate(&self) -> f64 {
+        if self.tests == 0 {
+            0.0
+        } else {
+            self.passes as f64 / self.tests as f64
+        }
+    }
+}
+
+/// Signed difference between two sets of results
+#[derive(Deserialize, Serialize, Clone, Copy, Hash, PartialEq, Eq, Debug, Default)]
+pub struct RunDelta {
+    pub tests: i64,
+    pub passes: i64,
+    pub failures: i64,
+}
+
+impl RunDelta {
+    /// How the results changed going from `from` to `to`
+    pub fn new(from: &RunResults, to: &RunResults) -> RunDelta {
+        RunDelta {
+            tests: to.tests as i64 - from.tests as i64,
+            passes: to.passes as i64 - from.passes as i64,
+            failures: to.failures as i64 - from.failures as i64,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct TestsuiteResult {
     pub name: String,
     pub commit: String,
     pub date: NaiveDate,
     pub results: RunResults,
+    /// Where this result was ingested from, e.g. `rust-gcc/testing/nightly_run.yml`.
+    /// Results produced before sources were tracked do not have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Identifies the run which produced this result, e.g. the id of a GitHub
+    /// workflow run, since a testsuite may be run several times a day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+    /// Status of each individual test, e.g. `PASS` or `FAIL`, keyed by test name.
+    /// Most testsuites only report the aggregated `results`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tests: Option<BTreeMap<String, String>>,
+}
+
+// The same result can be ingested from several sources, so the source it was
+// ingested from is left out of equality
+impl PartialEq for TestsuiteResult {
+    fn eq(&self, other: &TestsuiteResult) -> bool {
+        self.name == other.name
+            && self.commit == other.commit
+            && self.date == other.date
+            && self.results == other.results
+            && self.run == other.run
+            && self.tests == other.tests
+    }
+}
+
+impl Eq for TestsuiteResult {}
+
+impl Hash for TestsuiteResult {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.commit.hash(state);
+        self.date.hash(state);
+        self.results.hash(state);
+        self.run.hash(state);
+        self.tests.hash(state);
+    }
 }
 
 impl TestsuiteResult {
@@ -22,4 +93,131 @@ impl TestsuiteResult {
     pub fn from_bytes(bytes: &[u8]) -> Result<TestsuiteResult, serde_json::Error> {
         serde_json::from_slice(bytes)
     }
+
+    /// Parse a file containing either a single result or an array of results
+    pub fn all_from_bytes(bytes: &[u8]) -> Result<Vec<TestsuiteResult>, serde_json::Error> {
+        match serde_json::from_slice(bytes)? {
+            serde_json::Value::Array(results) => {
+                results.into_iter().map(serde_json::from_value).collect()
+            }
+            result => Ok(vec![serde_json::from_value(result)?]),
+        }
+    }
+
+    /// Whether both results come from the same run of the same testsuite, i.e.
+    /// have the same name, date, commit and run id
+    pub fn same_run(&self, other: &TestsuiteResult) -> bool {
+        self.name == other.name
+            && self.date == other.date
+            && self.commit == other.commit
+            && self.run == other.run
+    }
+}
+
+/// Subset of the results matching a query, along with the total number of
+/// matching results so that clients can page through them
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Page<T> {
+    pub total: usize,
+    pub results: Vec<T>,
+}
+
+/// Current state of a testsuite: its latest result and how it changed since the
+/// result before it, if any
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Summary {
+    pub name: String,
+    pub latest: TestsuiteResult,
+    pub previous: Option<TestsuiteResult>,
+    pub delta: Option<RunDelta>,
+}
+
+/// A test whose status differs between two results. The status is `None` if the
+/// test is absent from that result
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct TestChange {
+    pub name: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// Everything that changed between two results of the same testsuite
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct Diff {
+    pub from: TestsuiteResult,
+    pub to: TestsuiteResult,
+    pub from_commit: String,
+    pub to_commit: String,
+    pub delta: RunDelta,
+    /// Difference between the pass rates of both results
+    pub pass_rate_change: f64,
+    /// Tests whose status changed, only available if both results contain the
+    /// status of each test
+    pub changed_tests: Option<Vec<TestChange>>,
+}
+
+impl Diff {
+    pub fn new(from: TestsuiteResult, to: TestsuiteResult) -> Diff {
+        let changed_tests = match (&from.tests, &to.tests) {
+            (Some(before), Some(after)) => Some(
+                before
+                    .keys()
+                    .chain(after.keys())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .filter(|name| before.get(*name) != after.get(*name))
+                    .map(|name| TestChange {
+                        name: name.clone(),
+                        from: before.get(name).cloned(),
+                        to: after.get(name).cloned(),
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        };
+
+        Diff {
+            from_commit: from.commit.clone(),
+            to_commit: to.commit.clone(),
+            delta: RunDelta::new(&from.results, &to.results),
+            pass_rate_change: to.results.pass_rate() - from.results.pass_rate(),
+            changed_tests,
+            from,
+            to,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RegressionKind {
+    PassesDropped,
+    FailuresRose,
+    TestsChanged,
+}
+
+/// A change between two consecutive results of a testsuite which is worse than
+/// what is tolerated. The regression was introduced by a commit between
+/// `from_commit`, excluded, and `to_commit`, included
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Regression {
+    pub name: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub from_commit: String,
+    pub to_commit: String,
+    pub delta: RunDelta,
+    pub kinds: Vec<RegressionKind>,
+}
+
+/// Different results for the same run of a testsuite, e.g. because two sources
+/// produced it, which a maintainer needs to clean up
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Conflict {
+    pub name: String,
+    pub date: NaiveDate,
+    pub commit: String,
+    pub run: Option<String>,
+    /// Every result of the run. The first one is the one being served
+    pub results: Vec<TestsuiteResult>,
 }
@@ -8,7 +8,7 @@ use wasm_rs_dbg::dbg;
 use web_sys::HtmlCanvasElement;
 use yew::prelude::*;
 
-use common::TestsuiteResult;
+use common::{Page, TestsuiteResult};
 
 #[derive(Debug, Clone)]
 enum Error {
@@ -32,9 +32,9 @@ async fn fetch_testsuites(base_url: &str) -> Result<Vec<String>, reqwasm::Error>
 async fn fetch_results(base_url: &str, key: &str) -> Result<Vec<TestsuiteResult>, reqwasm::Error> {
     let url = format!("{base_url}/api/testsuites/{key}");
     let response = reqwasm::http::Request::get(&url).send().await?;
-    let testsuites: Vec<TestsuiteResult> = response.json().await?;
+    let testsuites: Page<TestsuiteResult> = response.json().await?;
 
-    Ok(testsuites)
+    Ok(testsuites.results)
 }
 
 enum CacheMsg {